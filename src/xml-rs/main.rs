@@ -0,0 +1,266 @@
+/*
+ * Copyright (C) 2014 Maciej Piechotka
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+ * THE SOFTWARE.
+ */
+
+#[comment = "Command-line tool for formatting, checking and querying XML."];
+#[crate_id = "github.com/uzytkownik/xml-rs#xml-rs:0.1"];
+#[crate_type = "bin"];
+#[license = "MIT"];
+
+extern mod extra;
+extern mod xml;
+
+use std::io::io_error;
+use extra::getopts::{getopts, optflag, optmulti, optopt, Opt};
+use xml::{Document, IoReadError, ParseError, ParseReadError};
+use xml::validation::{DtdSchema, RelaxNGSchema, Validator, XmlSchema};
+use xml::xpath::{AttributeXPathNode, BooleanXPathValue, ChildXPathNode, DocumentXPathNode,
+                 NamespaceXPathNode, NodesXPathValue, NumberXPathValue, StringXPathValue};
+
+static USAGE: &'static str = "Usage: xml-rs <command> [options] [files]
+
+Files default to standard input, which can also be given as `-`.
+
+Commands:
+    fmt                         Print documents indented
+    check                       Check documents are well-formed
+    validate --xsd|--rng|--dtd <schema>
+                                Validate documents against schema
+    xpath [--ns <prefix>=<uri>]... <expr>
+                                Print results of the expression
+    c14n [--with-comments]      Print documents in canonical form
+";
+
+fn main() {
+    let args = std::os::args();
+    if args.len() < 2 {
+        usage();
+        std::os::set_exit_status(2);
+        return;
+    }
+    let rest = args.slice_from(2);
+    let ok = match args[1].as_slice() {
+        "fmt" => fmt(rest),
+        "check" => check(rest),
+        "validate" => validate(rest),
+        "xpath" => xpath(rest),
+        "c14n" => c14n(rest),
+        "help" | "--help" | "-h" => {
+            print(USAGE);
+            true
+        }
+        command => {
+            error(format!("unknown command `{}`", command).as_slice());
+            usage();
+            false
+        }
+    };
+    if !ok {
+        std::os::set_exit_status(1);
+    }
+}
+
+fn fmt(args: &[~str]) -> bool {
+    with_files(args, ~[], |_, files| {
+        for_each_document(files, |_, doc| {
+            print(doc.to_formatted_string().as_slice());
+            true
+        })
+    })
+}
+
+fn check(args: &[~str]) -> bool {
+    with_files(args, ~[], |_, files| for_each_document(files, |_, _| true))
+}
+
+fn validate(args: &[~str]) -> bool {
+    let opts = ~[optopt("xsd"), optopt("rng"), optopt("dtd")];
+    with_files(args, opts, |matches, files| {
+        let schema = match (matches.opt_str("xsd"), matches.opt_str("rng"), matches.opt_str("dtd")) {
+            (Some(path), None, None) => load_schema(path, |path| XmlSchema::from_path(path).map(|s| ~s as ~Validator)),
+            (None, Some(path), None) => load_schema(path, |path| RelaxNGSchema::from_path(path).map(|s| ~s as ~Validator)),
+            (None, None, Some(path)) => load_schema(path, |path| DtdSchema::from_path(path).map(|s| ~s as ~Validator)),
+            _ => {
+                error("exactly one of --xsd, --rng and --dtd is required");
+                None
+            }
+        };
+        match schema {
+            Some(schema) => for_each_document(files, |name, doc| {
+                match schema.validate(doc) {
+                    Ok(()) => {
+                        println!("{}: valid", name);
+                        true
+                    }
+                    Err(errors) => {
+                        report(name, errors);
+                        false
+                    }
+                }
+            }),
+            None => false
+        }
+    })
+}
+
+fn xpath(args: &[~str]) -> bool {
+    let opts = ~[optmulti("ns")];
+    with_files(args, opts, |matches, files| {
+        let mut namespaces = ~[];
+        for binding in matches.opt_strs("ns").iter() {
+            match binding.find('=') {
+                Some(i) => namespaces.push((binding.slice_to(i).to_owned(), binding.slice_from(i + 1).to_owned())),
+                None => {
+                    error(format!("invalid namespace binding `{}`, expected <prefix>=<uri>", *binding).as_slice());
+                    return false;
+                }
+            }
+        }
+        let namespaces = namespaces.iter().map(|&(ref prefix, ref uri)| (prefix.as_slice(), uri.as_slice())).to_owned_vec();
+        if files.is_empty() {
+            error("missing expression");
+            return false;
+        }
+        let expr = files[0].as_slice();
+        let files = if files.len() > 1 {files.slice_from(1).to_owned()} else {~[~"-"]};
+        for_each_document(files.as_slice(), |name, doc| {
+            match xml::xpath::evaluate(doc, expr, namespaces) {
+                Ok(NodesXPathValue(nodes)) => {
+                    for node in nodes.iter() {
+                        match *node {
+                            ChildXPathNode(child) => println(child.to_string().as_slice()),
+                            AttributeXPathNode(attr) => println!("{}=\"{}\"", attr.name_str(), escape(attr.value())),
+                            NamespaceXPathNode(Some(ref prefix), ref uri) => println!("xmlns:{}=\"{}\"", *prefix, escape(uri.clone())),
+                            NamespaceXPathNode(None, ref uri) => println!("xmlns=\"{}\"", escape(uri.clone())),
+                            DocumentXPathNode(_) => print(doc.to_string().as_slice())
+                        }
+                    }
+                    true
+                }
+                Ok(BooleanXPathValue(value)) => {
+                    println!("{}", value);
+                    true
+                }
+                Ok(NumberXPathValue(value)) => {
+                    println!("{}", value);
+                    true
+                }
+                Ok(StringXPathValue(value)) => {
+                    println(value.as_slice());
+                    true
+                }
+                Err(err) => {
+                    error(format!("{}: invalid expression: {}", name, err.message).as_slice());
+                    false
+                }
+            }
+        })
+    })
+}
+
+fn c14n(args: &[~str]) -> bool {
+    let opts = ~[optflag("with-comments")];
+    with_files(args, opts, |matches, files| {
+        let with_comments = matches.opt_present("with-comments");
+        for_each_document(files, |name, doc| {
+            let mut failure = None;
+            io_error::cond.trap(|err| failure = Some(err)).inside(|| {
+                std::io::stdout().write(doc.canonicalize(with_comments));
+            });
+            match failure {
+                Some(err) => {
+                    error(format!("{}: cannot write output: {}", name, err.desc).as_slice());
+                    false
+                }
+                None => true
+            }
+        })
+    })
+}
+
+// Parses options, passing remaining arguments or standard input as files.
+fn with_files(args: &[~str], opts: &[Opt], f: |&extra::getopts::Matches, &[~str]| -> bool) -> bool {
+    match getopts(args, opts) {
+        Ok(matches) => {
+            let files = if matches.free.is_empty() {~[~"-"]} else {matches.free.clone()};
+            f(&matches, files.as_slice())
+        }
+        Err(err) => {
+            error(err.to_err_msg().as_slice());
+            usage();
+            false
+        }
+    }
+}
+
+// Reads each file in turn, reporting whether all were read and handled.
+fn for_each_document(files: &[~str], f: |&str, &Document| -> bool) -> bool {
+    let mut ok = true;
+    for name in files.iter() {
+        let result = if name.as_slice() == "-" {
+            Document::read(&mut std::io::stdin())
+        } else {
+            Document::from_path(&Path::new(name.as_slice()))
+        };
+        ok = match result {
+            Ok(doc) => f(name.as_slice(), &doc),
+            Err(ParseReadError(err)) => {
+                error(format!("{}:{}:{}: {}", *name, err.line, err.column, err.message).as_slice());
+                false
+            }
+            Err(IoReadError(err)) => {
+                error(format!("{}: {}", *name, err.desc).as_slice());
+                false
+            }
+        } && ok;
+    }
+    ok
+}
+
+fn load_schema(path: ~str, load: |&Path| -> Result<~Validator, ~[ParseError]>) -> Option<~Validator> {
+    match load(&Path::new(path.as_slice())) {
+        Ok(schema) => Some(schema),
+        Err(errors) => {
+            report(path.as_slice(), errors);
+            None
+        }
+    }
+}
+
+fn report(name: &str, errors: ~[ParseError]) {
+    for err in errors.iter() {
+        error(format!("{}:{}:{}: {}", name, err.line, err.column, err.message).as_slice());
+    }
+}
+
+fn escape(value: ~str) -> ~str {
+    value.replace("&", "&amp;").replace("<", "&lt;").replace("\"", "&quot;")
+}
+
+fn error(message: &str) {
+    let mut err = std::io::stderr();
+    err.write(format!("xml-rs: {}\n", message).as_bytes());
+}
+
+fn usage() {
+    let mut err = std::io::stderr();
+    err.write(USAGE.as_bytes());
+}
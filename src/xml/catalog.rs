@@ -0,0 +1,258 @@
+/*
+ * Copyright (C) 2014 Maciej Piechotka
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+ * THE SOFTWARE.
+ */
+/*!
+ * OASIS XML catalogs mapping public identifiers, system identifiers and
+ * URIs to local resources.
+ */
+
+use std::io::File;
+use std::io::io_error;
+use std::libc::{c_char, c_int, c_void};
+use std::local_data;
+use std::unstable::atomics::{AtomicUint, INIT_ATOMIC_UINT, SeqCst};
+use super::{Document, NamedNode, ParseError, ParseReadError, ReadError};
+use super::resolver::{Resource, ResourceResolver, ReaderResource, DeniedResource};
+use ffi;
+
+static CATALOG_NS: &'static str = "urn:oasis:names:tc:entity:xmlns:xml:catalog";
+static XML_NS: &'static str = "http://www.w3.org/XML/1998/namespace";
+// Catalogs are served from memory to libxml2 under URLs with this prefix.
+static URL_PREFIX: &'static str = "xml-rs-catalog:";
+
+/**
+ * A catalog, used for lookups or as resolver of a single parse. It is a
+ * local catalog of libxml2, independent of its global catalogs. Catalogs
+ * it refers to by nextCatalog and delegate entries are read by libxml2
+ * when a lookup reaches them.
+ */
+pub struct Catalog {
+    priv catalogs: *c_void,
+    // libxml2 keeps parsed catalogs by URL, so each catalog has its own
+    priv url: ~str,
+    // Serialized catalog, read by libxml2 on the first lookup
+    priv content: ~[u8]
+}
+
+// Address of the catalog looked up in current task
+local_data_key!(LOOKUP: uint)
+
+// Catalog being read by libxml2
+struct Input {
+    content: ~[u8],
+    position: uint
+}
+
+/**
+ * Install input callbacks serving catalogs to libxml2. It has to be called
+ * once, under the initialization lock.
+ */
+pub unsafe fn install() {
+    ffi::xmlRegisterInputCallbacks(input_match, input_open, input_read, input_close);
+}
+
+impl Catalog {
+    /**
+     * Load the catalog from file. Relative URIs are resolved against its
+     * location.
+     */
+    pub fn from_path(path: &Path) -> Result<Catalog, ReadError> {
+        match Document::from_path(path) {
+            Ok(doc) => {
+                let base = format!("file://{}", std::os::make_absolute(path).display());
+                Catalog::from_document(doc, base.as_slice()).map_err(|err| ParseReadError(err))
+            },
+            Err(err) => Err(err)
+        }
+    }
+
+    /**
+     * Load the catalog from string. Relative URIs are resolved against the
+     * base URI.
+     */
+    pub fn from_str(s: &str, base: &str) -> Result<Catalog, ParseError> {
+        match Document::from_str(s) {
+            Ok(doc) => Catalog::from_document(doc, base),
+            Err(err) => Err(err)
+        }
+    }
+
+    fn from_document(doc: Document, base: &str) -> Result<Catalog, ParseError> {
+        static mut COUNTER: AtomicUint = INIT_ATOMIC_UINT;
+        let base = match doc.get_root_element() {
+            Some(root) if root.has_name("catalog", Some(CATALOG_NS)) => {
+                let own_base = root.attribute_iter().find(|attr| {
+                    attr.name_str() == "base" && attr.namespace().map_default(false, |ns| ns.href_str() == XML_NS)
+                });
+                own_base.map_default(base.to_owned(), |attr| super::resolve_uri(attr.value().as_slice(), base))
+            },
+            _ => return Err(ParseError {
+                message: ~"Root element is not an OASIS catalog",
+                line: 1,
+                column: 0
+            })
+        };
+        // Otherwise libxml2 would resolve URIs against the URL it reads the
+        // catalog from
+        unsafe {
+            let root = ffi::xmlDocGetRootElement(doc.doc);
+            let ns = XML_NS.with_c_str(|href| ffi::xmlSearchNsByHref(doc.doc, root, href as *ffi::xmlChar));
+            "base".with_c_str(|name| base.with_c_str(|value| {
+                ffi::xmlSetNsProp(root, ns, name as *ffi::xmlChar, value as *ffi::xmlChar)
+            }));
+        }
+        let url = format!("{}{}", URL_PREFIX, unsafe {COUNTER.fetch_add(1, SeqCst)});
+        let catalogs = url.with_c_str(|url| unsafe {ffi::xmlCatalogAddLocal(std::ptr::null(), url as *ffi::xmlChar)});
+        Ok(Catalog {catalogs: catalogs, url: url, content: doc.to_bytes()})
+    }
+
+    /**
+     * Resolve the external identifier, consisting of public identifier and
+     * system identifier.
+     */
+    pub fn resolve_external(&self, public_id: Option<&str>, system_id: Option<&str>) -> Option<~str> {
+        with_opt_c_str(public_id, |public_id| with_opt_c_str(system_id, |system_id| {
+            self.lookup(|| unsafe {ffi::xmlCatalogLocalResolve(self.catalogs, public_id, system_id)})
+        }))
+    }
+
+    /**
+     * Resolve the public identifier.
+     */
+    pub fn resolve_public(&self, public_id: &str) -> Option<~str> {
+        self.resolve_external(Some(public_id), None)
+    }
+
+    /**
+     * Resolve the system identifier.
+     */
+    pub fn resolve_system(&self, system_id: &str) -> Option<~str> {
+        self.resolve_external(None, Some(system_id))
+    }
+
+    /**
+     * Resolve the URI, such as schema location or namespace name.
+     */
+    pub fn resolve_uri(&self, uri: &str) -> Option<~str> {
+        uri.with_c_str(|uri| {
+            self.lookup(|| unsafe {ffi::xmlCatalogLocalResolveURI(self.catalogs, uri as *ffi::xmlChar)})
+        })
+    }
+
+    // Runs the lookup with the catalog served to libxml2 in case it reads
+    // it, taking the resolved URI.
+    fn lookup(&self, f: || -> *ffi::xmlChar) -> Option<~str> {
+        let previous = local_data::pop(LOOKUP);
+        local_data::set(LOOKUP, self as *Catalog as uint);
+        let resolved = f();
+        local_data::pop(LOOKUP);
+        previous.map(|previous| local_data::set(LOOKUP, previous));
+        unsafe {take_str(resolved)}
+    }
+}
+
+/**
+ * Resolver loading resources mapped by the catalog to local files and
+ * denying all others.
+ */
+impl ResourceResolver for Catalog {
+    fn resolve(&self, public_id: Option<&str>, system_id: Option<&str>, _: Option<&str>) -> Resource {
+        match self.resolve_external(public_id, system_id) {
+            Some(uri) => {
+                match super::uri_to_path(uri.as_slice()) {
+                    Some(path) => {
+                        let mut failed = false;
+                        let file = io_error::cond.trap(|_| failed = true).inside(|| File::open(&path));
+                        match file {
+                            Some(file) if !failed => ReaderResource(~file as ~Reader),
+                            _ => DeniedResource(format!("Failed to open {}", uri))
+                        }
+                    },
+                    None => DeniedResource(format!("Catalog maps resource to non-local {}", uri))
+                }
+            },
+            None => DeniedResource(format!("{} not found in catalog", system_id.or(public_id).unwrap_or("Resource")))
+        }
+    }
+}
+
+impl Drop for Catalog {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::xmlCatalogFreeLocal(self.catalogs);
+        }
+    }
+}
+
+// Catalog looked up in current task, if any.
+fn current_catalog() -> Option<*Catalog> {
+    local_data::get(LOOKUP, |lookup| lookup.map(|&address| address as *Catalog))
+}
+
+extern "C" fn input_match(filename: *c_char) -> c_int {
+    unsafe {
+        let filename = std::str::raw::from_c_str(filename);
+        current_catalog().map_default(false, |catalog| (*catalog).url == filename) as c_int
+    }
+}
+
+extern "C" fn input_open(_: *c_char) -> *c_void {
+    unsafe {
+        match current_catalog() {
+            Some(catalog) => std::cast::transmute(~Input {content: (*catalog).content.clone(), position: 0}),
+            None => std::ptr::null()
+        }
+    }
+}
+
+extern "C" fn input_read(context: *c_void, buffer: *mut c_char, len: c_int) -> c_int {
+    unsafe {
+        let input = &mut *(context as *mut Input);
+        std::vec::raw::mut_buf_as_slice(buffer as *mut u8, len as uint, |buffer| {
+            let count = buffer.copy_from(input.content.slice_from(input.position));
+            input.position += count;
+            count as c_int
+        })
+    }
+}
+
+extern "C" fn input_close(context: *c_void) -> c_int {
+    unsafe {
+        let _input: ~Input = std::cast::transmute(context);
+    }
+    0
+}
+
+fn with_opt_c_str<T>(s: Option<&str>, f: |*ffi::xmlChar| -> T) -> T {
+    match s {
+        Some(s) => s.with_c_str(|s| f(s as *ffi::xmlChar)),
+        None => f(std::ptr::null())
+    }
+}
+
+// Takes ownership of string allocated by libxml2.
+unsafe fn take_str(s: *ffi::xmlChar) -> Option<~str> {
+    super::ptr_to_option(s).map(|s| {
+        let owned = super::owned_str(s);
+        super::xml_free(s);
+        owned
+    })
+}
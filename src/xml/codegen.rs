@@ -0,0 +1,675 @@
+/*
+ * Copyright (C) 2014 Maciej Piechotka
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+ * THE SOFTWARE.
+ */
+
+/*!
+ * Generation of Rust types from XML schemas.
+ *
+ * Complex types become structs and simple types with enumerations become
+ * enums, implementing `Encodable` and `Decodable` with the conventions of
+ * `xml::serialize`. Other simple types become aliases of built-in types.
+ * Alternatives of a choice become optional fields and fields referring to
+ * an enclosing type are boxed. For each global element `read_<name>` and
+ * `write_<name>` functions are generated.
+ *
+ * The schema is compiled by libxml2 first, so only valid schemas are
+ * translated. Included and imported schemas are loaded relative to the URL
+ * of the schema document. Child elements are expected in the target
+ * namespace if `elementFormDefault` is `qualified` and in no namespace
+ * otherwise; declarations deviating from that are not supported.
+ *
+ * The generated module requires `extern mod extra` and `extern mod xml`
+ * in the crate root.
+ */
+
+use std::hashmap::{HashMap, HashSet};
+use super::{BorrowedElement, Document, NamedNode, TreeNode};
+use super::validation::XmlSchema;
+
+static XSD_NS: &'static str = "http://www.w3.org/2001/XMLSchema";
+
+static KEYWORDS: &'static [&'static str] = &[
+    "as", "break", "continue", "do", "else", "enum", "extern", "false", "fn", "for", "if", "impl",
+    "in", "let", "loop", "match", "mod", "mut", "priv", "proc", "pub", "ref", "return", "self",
+    "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "while"
+];
+
+macro_rules! check(
+    ($e:expr) => (match $e {
+        Ok(value) => value,
+        Err(err) => return Err(err)
+    })
+)
+
+/**
+ * Construct of the schema which cannot be translated.
+ */
+#[deriving(Clone, Eq)]
+pub struct SchemaError {
+    /// Description of the problem.
+    message: ~str,
+    /// Line of the schema element, 0 if unknown.
+    line: uint
+}
+
+// Namespace, empty if none, and local name of schema component
+type QName = (~str, ~str);
+
+struct Field {
+    xml_name: ~str,
+    ident: ~str,
+    ty: ~str
+}
+
+struct Generator<'r> {
+    types: HashMap<QName, BorrowedElement<'r>>,
+    elements: HashMap<QName, BorrowedElement<'r>>,
+    attributes: HashMap<QName, BorrowedElement<'r>>,
+    groups: HashMap<QName, BorrowedElement<'r>>,
+    attribute_groups: HashMap<QName, BorrowedElement<'r>>,
+    // Rust names of named types
+    names: HashMap<QName, ~str>,
+    // Rust names of anonymous types by their declaration
+    anonymous: HashMap<BorrowedElement<'r>, ~str>,
+    generated: HashSet<~str>,
+    // Complex types being generated, boxed when referred to
+    in_progress: ~[~str],
+    target_ns: ~str,
+    // Target namespaces of included and imported schemas by URL
+    namespaces: HashMap<~str, ~str>,
+    // Namespace of child elements
+    child_ns: ~str,
+    output: ~str
+}
+
+impl ToStr for SchemaError {
+    fn to_str(&self) -> ~str {
+        format!("{}: {}", self.line, self.message)
+    }
+}
+
+/**
+ * Generate Rust module source from schema document.
+ */
+pub fn generate(schema: &Document) -> Result<~str, SchemaError> {
+    let root = match schema.get_root_element() {
+        Some(root) if root.has_name("schema", Some(XSD_NS)) => root,
+        _ => return Err(SchemaError {message: ~"Root element is not xs:schema", line: 0})
+    };
+    match XmlSchema::from_document(schema) {
+        Ok(_) => {},
+        Err(errors) => return Err(SchemaError {message: errors[0].message.clone(), line: errors[0].line})
+    }
+    let target_ns = attribute(&root, "targetNamespace").unwrap_or(~"");
+    let base = schema.url().unwrap_or_else(|| format!("file://{}/", std::os::getcwd().display()));
+    let mut seen = HashSet::new();
+    seen.insert(base.clone());
+    let mut schemas = ~[];
+    check!(load_schemas(&root, base.as_slice(), target_ns.as_slice(), &mut seen, &mut schemas));
+    let qualified = attribute(&root, "elementFormDefault") == Some(~"qualified");
+    let mut generator = Generator {
+        types: HashMap::new(),
+        elements: HashMap::new(),
+        attributes: HashMap::new(),
+        groups: HashMap::new(),
+        attribute_groups: HashMap::new(),
+        names: HashMap::new(),
+        anonymous: HashMap::new(),
+        generated: HashSet::new(),
+        in_progress: ~[],
+        target_ns: target_ns.clone(),
+        namespaces: HashMap::new(),
+        child_ns: if qualified {target_ns.clone()} else {~""},
+        output: ~""
+    };
+    // Types and elements of the target namespace are generated, others on use
+    let mut roots = ~[root.clone()];
+    generator.register(&root, target_ns.as_slice());
+    for &(ref doc, ref ns) in schemas.iter() {
+        let schema_root = doc.get_root_element().unwrap();
+        generator.register(&schema_root, ns.as_slice());
+        doc.url().map(|url| generator.namespaces.insert(url, ns.clone()));
+        if *ns == target_ns {
+            roots.push(schema_root);
+        }
+    }
+    let (read, write) = if qualified {("try_decode", "encode")} else {("try_decode_unqualified", "encode_unqualified")};
+    generator.output.push_str("// Generated from XML schema by xml::codegen. Do not edit.\n\n");
+    generator.output.push_str("#[allow(unused_imports)];\n\n");
+    generator.output.push_str("use extra::serialize::{Decodable, Decoder, Encodable, Encoder};\n");
+    generator.output.push_str("use xml::{BorrowedElement, Document};\n");
+    generator.output.push_str("use xml::serialize;\n");
+    generator.output.push_str(format!("use xml::serialize::\\{DecodeError, {}, {}\\};\n", read, write));
+    for schema_root in roots.iter() {
+        for child in schema_children(schema_root).move_iter() {
+            match (child.name_str(), attribute(&child, "name")) {
+                ("simpleType", Some(name)) | ("complexType", Some(name)) => {
+                    check!(generator.named_type((target_ns.clone(), name), &child));
+                },
+                _ => {}
+            }
+        }
+    }
+    for schema_root in roots.iter() {
+        for child in schema_children(schema_root).move_iter() {
+            match (child.name_str(), attribute(&child, "name")) {
+                ("element", Some(name)) => {
+                    let ty = check!(generator.element_type(&child, ""));
+                    let ident = field_ident(name.as_slice());
+                    let ns = if target_ns.is_empty() {~"None"} else {format!("Some({})", quote(target_ns.as_slice()))};
+                    generator.output.push_str(format!(
+                        "\n/// Read `{0}` element.\npub fn read_{1}(element: BorrowedElement) -> Result<{2}, DecodeError> \\{\n    {5}(element)\n\\}\n\
+                         \n/// Write `{0}` element as document.\npub fn write_{1}(value: &{2}) -> Document \\{\n    {6}(value, {3}, {4})\n\\}\n",
+                        name, ident.trim_right_chars(&'_'), ty, quote(name.as_slice()), ns, read, write));
+                },
+                _ => {}
+            }
+        }
+    }
+    Ok(generator.output)
+}
+
+// Loads schemas included and imported by the schema, recursively, with
+// their target namespaces. Included schemas without target namespace take
+// the one of the including schema.
+fn load_schemas(schema: &BorrowedElement, base: &str, target_ns: &str, seen: &mut HashSet<~str>,
+                schemas: &mut ~[(Document, ~str)]) -> Result<(), SchemaError> {
+    for child in schema_children(schema).move_iter() {
+        let location = match (child.name_str(), attribute(&child, "schemaLocation")) {
+            ("include", Some(location)) | ("import", Some(location)) => location,
+            ("redefine", _) | ("override", _) => return Err(error(format!("Unsupported xs:{}", child.name_str()), &child)),
+            _ => continue
+        };
+        let uri = super::resolve_uri(location.as_slice(), base);
+        if !seen.insert(uri.clone()) {
+            continue;
+        }
+        let doc = match super::uri_to_path(uri.as_slice()).map(|path| Document::from_path(&path)) {
+            Some(Ok(doc)) => doc,
+            _ => return Err(error(format!("Failed to load schema {}", uri), &child))
+        };
+        let ns = match doc.get_root_element().and_then(|root| attribute(&root, "targetNamespace")) {
+            Some(ns) => ns,
+            None if child.name_str() == "include" => target_ns.to_owned(),
+            None => ~""
+        };
+        check!(load_schemas(&doc.get_root_element().unwrap(), uri.as_slice(), ns.as_slice(), seen, schemas));
+        schemas.push((doc, ns));
+    }
+    Ok(())
+}
+
+impl<'r> Generator<'r> {
+    // Registers global components of the schema document
+    fn register(&mut self, schema: &BorrowedElement<'r>, ns: &str) {
+        for child in schema_children(schema).move_iter() {
+            let name = match attribute(&child, "name") {
+                Some(name) => (ns.to_owned(), name),
+                None => continue
+            };
+            match child.name_str() {
+                "complexType" | "simpleType" => {self.types.insert(name, child);},
+                "element" => {self.elements.insert(name, child);},
+                "attribute" => {self.attributes.insert(name, child);},
+                "group" => {self.groups.insert(name, child);},
+                "attributeGroup" => {self.attribute_groups.insert(name, child);},
+                _ => {}
+            }
+        }
+    }
+
+    fn simple_type(&mut self, name: ~str, definition: &BorrowedElement) -> Result<(), SchemaError> {
+        if !self.generated.insert(name.clone()) {
+            return Ok(());
+        }
+        let restriction = schema_children(definition).move_iter().find(|child| child.name_str() == "restriction");
+        let values = restriction.as_ref().map_default(~[], |restriction| {
+            schema_children(restriction).move_iter().filter(|child| child.name_str() == "enumeration")
+                                        .filter_map(|child| attribute(&child, "value")).to_owned_vec()
+        });
+        if values.is_empty() {
+            let base = match restriction.as_ref().and_then(|restriction| attribute(restriction, "base")) {
+                Some(base) => check!(self.type_ref(base.as_slice(), restriction.get_ref())),
+                None => ~"~str"
+            };
+            self.output.push_str(format!("\npub type {} = {};\n", name, base));
+            return Ok(());
+        }
+        let mut variants: ~[~str] = ~[];
+        for value in values.iter() {
+            let mut variant = format!("{}{}", type_name(value.as_slice()), name);
+            while variants.contains(&variant) {
+                variant.push_char('_');
+            }
+            variants.push(variant);
+        }
+        let mut out = format!("\n\\#[deriving(Clone, Eq)]\npub enum {} \\{\n", name);
+        out.push_str(variants.map(|variant| format!("    {}", *variant)).connect(",\n"));
+        out.push_str(format!("\n\\}\n\nimpl<E: Encoder> Encodable<E> for {} \\{\n", name));
+        out.push_str("    fn encode(&self, e: &mut E) {\n        e.emit_str(match *self {\n");
+        out.push_str(variants.iter().zip(values.iter()).map(|(variant, value)| {
+            format!("            {} => {}", *variant, quote(value.as_slice()))
+        }).to_owned_vec().connect(",\n"));
+        out.push_str(format!("\n        \\})\n    \\}\n\\}\n\nimpl<'r> Decodable<serialize::Decoder<'r>> for {} \\{\n", name));
+        out.push_str(format!("    fn decode(d: &mut serialize::Decoder<'r>) -> {} \\{\n", name));
+        out.push_str("        let value = d.read_str();\n        match value.trim() {\n");
+        for (variant, value) in variants.iter().zip(values.iter()) {
+            out.push_str(format!("            {} => {},\n", quote(value.as_slice()), *variant));
+        }
+        out.push_str(format!("            other => \\{\n                d.report_error(format!(\"Invalid {} '\\{\\}'\", other));\n", name));
+        out.push_str(format!("                {}\n            \\}\n        \\}\n    \\}\n\\}\n", variants[0]));
+        self.output.push_str(out);
+        Ok(())
+    }
+
+    fn complex_type(&mut self, name: ~str, definition: &BorrowedElement<'r>) -> Result<(), SchemaError> {
+        if !self.generated.insert(name.clone()) {
+            return Ok(());
+        }
+        let mut fields = ~[];
+        self.in_progress.push(name.clone());
+        let result = self.content(name.as_slice(), definition, &mut fields);
+        self.in_progress.pop();
+        check!(result);
+        let mut out = format!("\n\\#[deriving(Clone, Eq)]\npub struct {} \\{\n", name);
+        out.push_str(fields.map(|field| format!("    {}: {}", field.ident, field.ty)).connect(",\n"));
+        out.push_str(format!("\n\\}\n\nimpl<E: Encoder> Encodable<E> for {} \\{\n", name));
+        out.push_str(format!("    fn encode(&self, e: &mut E) \\{\n        e.emit_struct({}, {}, |e| \\{\n",
+                             quote(name.as_slice()), fields.len()));
+        for (idx, field) in fields.iter().enumerate() {
+            out.push_str(format!("            e.emit_struct_field({}, {}, |e| self.{}.encode(e));\n",
+                                 quote(field.xml_name.as_slice()), idx, field.ident));
+        }
+        out.push_str(format!("        \\})\n    \\}\n\\}\n\nimpl<'r> Decodable<serialize::Decoder<'r>> for {} \\{\n", name));
+        out.push_str(format!("    fn decode(d: &mut serialize::Decoder<'r>) -> {} \\{\n", name));
+        out.push_str(format!("        d.read_struct({}, {}, |d| {} \\{\n", quote(name.as_slice()), fields.len(), name));
+        out.push_str(fields.iter().enumerate().map(|(idx, field)| {
+            format!("            {}: d.read_struct_field({}, {}, |d| Decodable::decode(d))",
+                    field.ident, quote(field.xml_name.as_slice()), idx)
+        }).to_owned_vec().connect(",\n"));
+        out.push_str("\n        })\n    }\n}\n");
+        self.output.push_str(out);
+        Ok(())
+    }
+
+    // Collects fields of complex type definition or its extension
+    fn content(&mut self, name: &str, definition: &BorrowedElement<'r>, fields: &mut ~[Field]) -> Result<(), SchemaError> {
+        for child in schema_children(definition).move_iter() {
+            match child.name_str() {
+                "sequence" | "all" | "choice" => check!(self.particles(name, &child, false, false, fields)),
+                "group" => check!(self.group_ref(name, &child, false, false, fields)),
+                "attribute" | "attributeGroup" => check!(self.attribute_uses(name, &child, fields)),
+                "simpleContent" | "complexContent" => {
+                    let derivation = match schema_children(&child).move_iter().find(|c| {
+                        c.name_str() == "extension" || c.name_str() == "restriction"
+                    }) {
+                        Some(derivation) => derivation,
+                        None => return Err(error(~"Missing derivation", &child))
+                    };
+                    let base = match attribute(&derivation, "base") {
+                        Some(base) => base,
+                        None => return Err(error(~"Missing base type", &derivation))
+                    };
+                    if child.name_str() == "simpleContent" {
+                        let ty = check!(self.type_ref(base.as_slice(), &derivation));
+                        fields.push(Field {xml_name: ~"_text", ident: ~"text", ty: ty});
+                    } else {
+                        let base_name = self.qname(base.as_slice(), &derivation);
+                        let base_definition = match self.types.find(&base_name) {
+                            Some(definition) if definition.name_str() == "complexType" => definition.clone(),
+                            _ => return Err(error(~"Base type is not a complex type", &derivation))
+                        };
+                        if derivation.name_str() == "extension" {
+                            check!(self.content(name, &base_definition, fields));
+                        }
+                    }
+                    check!(self.content(name, &derivation, fields));
+                },
+                "annotation" | "anyAttribute" | "extension" | "restriction" => {},
+                other => return Err(error(format!("Unsupported xs:{}", other), &child))
+            }
+        }
+        Ok(())
+    }
+
+    fn particles(&mut self, name: &str, group: &BorrowedElement<'r>, optional: bool, many: bool,
+                 fields: &mut ~[Field]) -> Result<(), SchemaError> {
+        let (group_optional, group_many) = occurrence(group);
+        let optional = optional || group_optional || group.name_str() == "choice";
+        let many = many || group_many;
+        for child in schema_children(group).move_iter() {
+            match child.name_str() {
+                "element" => {
+                    let (xml_name, ns, ty) = check!(self.declaration(name, &child));
+                    if ns != self.child_ns {
+                        return Err(error(format!("Unsupported namespace '{}' of element {}", ns, xml_name), &child));
+                    }
+                    let (element_optional, element_many) = occurrence(&child);
+                    let ty = if many || element_many {
+                        format!("~[{}]", ty)
+                    } else if optional || element_optional {
+                        format!("Option<{}>", ty)
+                    } else {
+                        ty
+                    };
+                    fields.push(Field {ident: field_ident(xml_name.as_slice()), xml_name: xml_name, ty: ty});
+                },
+                "sequence" | "choice" | "all" => check!(self.particles(name, &child, optional, many, fields)),
+                "group" => check!(self.group_ref(name, &child, optional, many, fields)),
+                "annotation" | "any" => {},
+                other => return Err(error(format!("Unsupported xs:{}", other), &child))
+            }
+        }
+        Ok(())
+    }
+
+    // Collects fields of the model group referred to by xs:group
+    fn group_ref(&mut self, name: &str, reference: &BorrowedElement, optional: bool, many: bool,
+                 fields: &mut ~[Field]) -> Result<(), SchemaError> {
+        let group = check!(self.find_ref(reference, "group"));
+        let (ref_optional, ref_many) = occurrence(reference);
+        for model in schema_children(&group).move_iter().filter(|child| child.name_str() != "annotation") {
+            check!(self.particles(name, &model, optional || ref_optional, many || ref_many, fields));
+        }
+        Ok(())
+    }
+
+    // Collects fields of attribute declaration or of attributes of the group
+    // referred to by xs:attributeGroup
+    fn attribute_uses(&mut self, name: &str, declaration: &BorrowedElement<'r>, fields: &mut ~[Field]) -> Result<(), SchemaError> {
+        if declaration.name_str() == "attributeGroup" {
+            let group = check!(self.find_ref(declaration, "attributeGroup"));
+            for child in schema_children(&group).move_iter() {
+                match child.name_str() {
+                    "attribute" | "attributeGroup" => check!(self.attribute_uses(name, &child, fields)),
+                    _ => {}
+                }
+            }
+            return Ok(());
+        }
+        let (xml_name, ty) = match (attribute(declaration, "name"), attribute(declaration, "ref")) {
+            (Some(xml_name), _) => {
+                let schema = schema_root(declaration);
+                if attribute(declaration, "form").or(attribute(&schema, "attributeFormDefault")) == Some(~"qualified") {
+                    return Err(error(format!("Unsupported qualified attribute {}", xml_name), declaration));
+                }
+                (xml_name, check!(self.element_type(declaration, name)))
+            },
+            (None, Some(reference)) => {
+                let (ns, local) = self.qname(reference.as_slice(), declaration);
+                if !ns.is_empty() {
+                    return Err(error(format!("Unsupported qualified attribute {}", reference), declaration));
+                }
+                let global = match self.attributes.find(&(ns, local.clone())) {
+                    Some(global) => global.clone(),
+                    None => return Err(error(format!("Unknown attribute {}", reference), declaration))
+                };
+                (local, check!(self.element_type(&global, "")))
+            },
+            (None, None) => return Err(error(~"Declaration without name", declaration))
+        };
+        let ty = if attribute(declaration, "use") == Some(~"required") {ty} else {format!("Option<{}>", ty)};
+        fields.push(Field {ident: field_ident(xml_name.as_slice()), xml_name: xml_name, ty: ty});
+        Ok(())
+    }
+
+    // Name, namespace and Rust type of element declaration
+    fn declaration(&mut self, parent: &str, declaration: &BorrowedElement<'r>) -> Result<(~str, ~str, ~str), SchemaError> {
+        match (attribute(declaration, "name"), attribute(declaration, "ref")) {
+            (Some(name), _) => {
+                let schema = schema_root(declaration);
+                let qualified = attribute(declaration, "form").or(attribute(&schema, "elementFormDefault")) == Some(~"qualified");
+                let ns = if qualified {self.target_namespace(declaration)} else {~""};
+                let ty = check!(self.element_type(declaration, parent));
+                Ok((name, ns, ty))
+            },
+            (None, Some(_)) => {
+                let global = check!(self.find_ref(declaration, "element"));
+                let (ns, local) = self.qname(attribute(declaration, "ref").unwrap().as_slice(), declaration);
+                let ty = check!(self.element_type(&global, ""));
+                Ok((local, ns, ty))
+            },
+            (None, None) => Err(error(~"Declaration without name", declaration))
+        }
+    }
+
+    // Rust type of element or attribute, generating anonymous types
+    fn element_type(&mut self, declaration: &BorrowedElement<'r>, parent: &str) -> Result<~str, SchemaError> {
+        match attribute(declaration, "type") {
+            Some(ty) => self.type_ref(ty.as_slice(), declaration),
+            None => {
+                match schema_children(declaration).move_iter().find(|child| {
+                    child.name_str() == "complexType" || child.name_str() == "simpleType"
+                }) {
+                    Some(definition) => {
+                        let local = attribute(declaration, "name").unwrap_or(~"");
+                        let name = self.anonymous_name(declaration, format!("{}{}", parent, type_name(local.as_slice())));
+                        if definition.name_str() == "complexType" {
+                            check!(self.complex_type(name.clone(), &definition));
+                        } else {
+                            check!(self.simple_type(name.clone(), &definition));
+                        }
+                        Ok(self.boxed(name))
+                    },
+                    None => Ok(~"~str")
+                }
+            }
+        }
+    }
+
+    // Rust type of named type, generating it on first use
+    fn named_type(&mut self, name: QName, context: &BorrowedElement) -> Result<~str, SchemaError> {
+        let definition = match self.types.find(&name) {
+            Some(definition) => definition.clone(),
+            None => return Err(error(format!("Unknown type {}", name.n1_ref().as_slice()), context))
+        };
+        let rust_name = self.rust_name(name);
+        if definition.name_str() == "complexType" {
+            check!(self.complex_type(rust_name.clone(), &definition));
+        } else {
+            check!(self.simple_type(rust_name.clone(), &definition));
+        }
+        Ok(self.boxed(rust_name))
+    }
+
+    fn type_ref(&mut self, qname: &str, context: &BorrowedElement) -> Result<~str, SchemaError> {
+        let name = self.qname(qname, context);
+        if name.n0_ref().as_slice() == XSD_NS {
+            return Ok(builtin_type(name.n1_ref().as_slice()).to_owned());
+        }
+        self.named_type(name, context)
+    }
+
+    // Global component referred to by the ref attribute
+    fn find_ref(&self, reference: &BorrowedElement, kind: &str) -> Result<BorrowedElement<'r>, SchemaError> {
+        let qname = match attribute(reference, "ref") {
+            Some(qname) => qname,
+            None => return Err(error(format!("xs:{} without ref", reference.name_str()), reference))
+        };
+        let components = match kind {
+            "group" => &self.groups,
+            "attributeGroup" => &self.attribute_groups,
+            _ => &self.elements
+        };
+        match components.find(&self.qname(qname.as_slice(), reference)) {
+            Some(component) => Ok(component.clone()),
+            None => Err(error(format!("Unknown {} {}", kind, qname), reference))
+        }
+    }
+
+    // Resolves QName in attribute value. Unprefixed names in included schemas
+    // without target namespace refer to the namespace of the including one.
+    fn qname(&self, qname: &str, context: &BorrowedElement) -> QName {
+        let ns = match namespace_of(qname, context) {
+            Some(ns) => ns,
+            None if attribute(&schema_root(context), "targetNamespace").is_none() => self.target_namespace(context),
+            None => ~""
+        };
+        (ns, local_name(qname).to_owned())
+    }
+
+    // Target namespace of the schema document the element is in
+    fn target_namespace(&self, element: &BorrowedElement) -> ~str {
+        match element.owner_document().url().and_then(|url| self.namespaces.find(&url).map(|ns| ns.clone())) {
+            Some(ns) => ns,
+            None => self.target_ns.clone()
+        }
+    }
+
+    // Unique Rust name of named type
+    fn rust_name(&mut self, name: QName) -> ~str {
+        match self.names.find(&name) {
+            Some(rust_name) => return rust_name.clone(),
+            None => {}
+        }
+        let rust_name = self.unused_name(type_name(name.n1_ref().as_slice()));
+        self.names.insert(name, rust_name.clone());
+        rust_name
+    }
+
+    // Unique Rust name of anonymous type, derived from the names of its
+    // declaration and of the parent type
+    fn anonymous_name(&mut self, declaration: &BorrowedElement<'r>, name: ~str) -> ~str {
+        match self.anonymous.find(declaration) {
+            Some(rust_name) => return rust_name.clone(),
+            None => {}
+        }
+        let rust_name = self.unused_name(name);
+        self.anonymous.insert(declaration.clone(), rust_name.clone());
+        rust_name
+    }
+
+    // Name not taken by named nor anonymous types, with underscores appended
+    fn unused_name(&self, name: ~str) -> ~str {
+        let mut name = name;
+        while self.names.iter().any(|(_, used)| *used == name) ||
+              self.anonymous.iter().any(|(_, used)| *used == name) {
+            name.push_char('_');
+        }
+        name
+    }
+
+    // Types referring to a type being generated are boxed to be finite
+    fn boxed(&self, name: ~str) -> ~str {
+        if self.in_progress.contains(&name) {format!("~{}", name)} else {name}
+    }
+}
+
+fn schema_children<'r>(element: &BorrowedElement<'r>) -> ~[BorrowedElement<'r>] {
+    element.child_elements().filter(|child| {
+        child.namespace().map_default(false, |ns| ns.href_str() == XSD_NS)
+    }).to_owned_vec()
+}
+
+// xs:schema element of the schema document
+fn schema_root<'r>(element: &BorrowedElement<'r>) -> BorrowedElement<'r> {
+    element.ancestors().last().unwrap_or(element.clone())
+}
+
+fn attribute(element: &BorrowedElement, name: &str) -> Option<~str> {
+    element.attribute_iter().find(|attr| attr.name_str() == name && attr.namespace().is_none()).map(|attr| attr.value())
+}
+
+// Whether the particle is optional and whether it can repeat
+fn occurrence(particle: &BorrowedElement) -> (bool, bool) {
+    let min = attribute(particle, "minOccurs").and_then(|min| from_str::<uint>(min.as_slice())).unwrap_or(1);
+    let many = match attribute(particle, "maxOccurs") {
+        Some(max) => max.as_slice() == "unbounded" || from_str::<uint>(max.as_slice()).map_default(false, |max| max > 1),
+        None => false
+    };
+    (min == 0, many)
+}
+
+fn namespace_of(qname: &str, context: &BorrowedElement) -> Option<~str> {
+    let prefix = qname.find(':').map(|idx| qname.slice_to(idx));
+    context.lookup_namespace_uri(prefix)
+}
+
+fn local_name<'a>(qname: &'a str) -> &'a str {
+    qname.rfind(':').map_default(qname, |idx| qname.slice_from(idx + 1))
+}
+
+fn builtin_type(name: &str) -> &'static str {
+    match name {
+        "boolean" => "bool",
+        "float" => "f32",
+        "double" | "decimal" => "f64",
+        "byte" => "i8",
+        "short" => "i16",
+        "int" => "i32",
+        "long" | "integer" | "negativeInteger" | "nonPositiveInteger" => "i64",
+        "unsignedByte" => "u8",
+        "unsignedShort" => "u16",
+        "unsignedInt" => "u32",
+        "unsignedLong" | "nonNegativeInteger" | "positiveInteger" => "u64",
+        _ => "~str"
+    }
+}
+
+// CamelCase identifier of type or variant
+fn type_name(name: &str) -> ~str {
+    let mut result = ~"";
+    for word in name.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()) {
+        let first = word.char_at(0);
+        result.push_char(if first.is_ascii() {first.to_ascii().to_upper().to_char()} else {first});
+        result.push_str(word.slice_from(word.char_range_at(0).next));
+    }
+    if result.is_empty() || result.char_at(0).is_digit() {
+        result = ~"V" + result;
+    }
+    result
+}
+
+// snake_case identifier of field
+fn field_ident(name: &str) -> ~str {
+    let mut result = ~"";
+    let mut prev_lower = false;
+    for c in name.chars() {
+        if c.is_alphanumeric() {
+            if c.is_uppercase() && prev_lower {
+                result.push_char('_');
+            }
+            result.push_char(if c.is_ascii() {c.to_ascii().to_lower().to_char()} else {c});
+            prev_lower = c.is_lowercase() || c.is_digit();
+        } else {
+            result.push_char('_');
+            prev_lower = false;
+        }
+    }
+    if result.is_empty() || result.char_at(0).is_digit() {
+        result = ~"_" + result;
+    }
+    if KEYWORDS.iter().any(|keyword| *keyword == result.as_slice()) {
+        result.push_char('_');
+    }
+    result
+}
+
+fn quote(s: &str) -> ~str {
+    format!("\"{}\"", s.escape_default())
+}
+
+fn error(message: ~str, element: &BorrowedElement) -> SchemaError {
+    SchemaError {
+        message: message,
+        line: element.line().unwrap_or(0)
+    }
+}
@@ -0,0 +1,469 @@
+/*
+ * Copyright (C) 2014 Maciej Piechotka
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+ * THE SOFTWARE.
+ */
+
+/*!
+ * Structural comparison of documents.
+ *
+ * Nodes are addressed by paths such as `/a[1]/b[2]/text()[1]`, with
+ * qualified names as written in the documents. Deleted nodes, updated
+ * nodes and sources of moves are addressed in the old tree, inserted nodes
+ * and destinations of moves in the new tree.
+ */
+
+use std::hash::Hash;
+use std::hashmap::HashMap;
+use ffi;
+use super::{BorrowedElement, Document, ElementChild, ElementElementChild, TextElementChild,
+            CDataElementChild, CommentElementChild, NamedNode, TextNode, TreeNode};
+
+/**
+ * Differences which are not reported.
+ */
+pub struct DiffOptions {
+    /// Skip text nodes consisting of whitespace and compare text trimmed.
+    ignore_whitespace: bool,
+    /// Skip comments.
+    ignore_comments: bool,
+    /// Do not report attributes appearing in different order.
+    ignore_attribute_order: bool,
+    /// Compare elements and attributes by namespace URI only.
+    ignore_prefixes: bool
+}
+
+/**
+ * Single change turning the old tree into the new one.
+ */
+#[deriving(Clone, Eq, ToStr)]
+pub enum Edit {
+    /// Node was inserted at path, with its serialization.
+    InsertEdit(~str, ~str),
+    /// Node at path was deleted.
+    DeleteEdit(~str),
+    /// Unchanged node moved from the path to the position in the new tree,
+    /// given as path of the new parent followed by `node()[N]`, N counting
+    /// all its child nodes.
+    MoveEdit(~str, ~str),
+    /// Text or comment at path changed from the old to the new content.
+    UpdateTextEdit(~str, ~str, ~str),
+    /// Attribute of element at path changed from the old to the new value,
+    /// None meaning that it is absent.
+    AttributeEdit(~str, ~str, Option<~str>, Option<~str>),
+    /// Attributes of element at path appear in different order.
+    AttributeOrderEdit(~str)
+}
+
+struct Differ<'a> {
+    options: &'a DiffOptions,
+    edits: ~[Edit],
+    // Signatures of compared elements by node address
+    signatures: HashMap<uint, u64>,
+    // Paths and signatures of unmatched nodes, inserted ones with their
+    // serialization and position
+    deleted: ~[(~str, Option<u64>)],
+    inserted: ~[(~str, Option<u64>, ~str, ~str)]
+}
+
+impl DiffOptions {
+    /**
+     * Options reporting all differences.
+     */
+    pub fn new() -> DiffOptions {
+        DiffOptions {
+            ignore_whitespace: false,
+            ignore_comments: false,
+            ignore_attribute_order: false,
+            ignore_prefixes: false
+        }
+    }
+}
+
+/**
+ * Compare root elements of documents.
+ */
+pub fn diff_documents(old: &Document, new: &Document, options: &DiffOptions) -> ~[Edit] {
+    match (old.get_root_element(), new.get_root_element()) {
+        (Some(old), Some(new)) => diff_elements(&old, &new, options),
+        _ => ~[]
+    }
+}
+
+/**
+ * Compare elements with their subtrees.
+ */
+pub fn diff_elements(old: &BorrowedElement, new: &BorrowedElement, options: &DiffOptions) -> ~[Edit] {
+    let mut differ = Differ {
+        options: options,
+        edits: ~[],
+        signatures: HashMap::new(),
+        deleted: ~[],
+        inserted: ~[]
+    };
+    let (old_path, new_path) = (element_path(old), element_path(new));
+    if key(&ElementElementChild(old.clone()), options) == key(&ElementElementChild(new.clone()), options) {
+        differ.compare(old, old_path.as_slice(), new, new_path.as_slice());
+    } else {
+        differ.deleted.push((old_path, None));
+        differ.inserted.push((new_path.clone(), None, ElementElementChild(new.clone()).to_string(), new_path));
+    }
+    differ.finish()
+}
+
+impl<'a> Differ<'a> {
+    fn compare(&mut self, old: &BorrowedElement, old_path: &str, new: &BorrowedElement, new_path: &str) {
+        self.compare_attributes(old, old_path, new);
+        let old_children = children(old, self.options);
+        let new_children = children(new, self.options);
+        let options = self.options;
+        let old_signatures = old_children.iter().map(|&(child, _, _)| self.signature(&child)).to_owned_vec();
+        let new_signatures = new_children.iter().map(|&(child, _, _)| self.signature(&child)).to_owned_vec();
+        let keys = |children: &~[(ElementChild, ~str, uint)]| {
+            children.map(|&(child, _, _)| key(&child, options))
+        };
+        let (old_keys, new_keys) = (keys(&old_children), keys(&new_children));
+        // Unchanged nodes anchor matching of changed ones by name
+        let mut anchors = lcs(old_signatures.as_slice(), new_signatures.as_slice());
+        anchors.push((old_children.len(), new_children.len()));
+        let mut pairs = ~[];
+        let (mut old_start, mut new_start) = (0, 0);
+        for &(old_end, new_end) in anchors.iter() {
+            for &(i, j) in lcs(old_keys.slice(old_start, old_end), new_keys.slice(new_start, new_end)).iter() {
+                pairs.push((old_start + i, new_start + j));
+            }
+            if old_end < old_children.len() {
+                pairs.push((old_end, new_end));
+            }
+            old_start = old_end + 1;
+            new_start = new_end + 1;
+        }
+        let mut old_matched = std::vec::from_elem(old_children.len(), false);
+        let mut new_matched = std::vec::from_elem(new_children.len(), false);
+        for &(i, j) in pairs.iter() {
+            old_matched[i] = true;
+            new_matched[j] = true;
+            if old_signatures[i] == new_signatures[j] {
+                continue;
+            }
+            let (old_child, ref old_step, _) = old_children[i];
+            let (new_child, ref new_step, _) = new_children[j];
+            let old_child_path = format!("{}/{}", old_path, *old_step);
+            let new_child_path = format!("{}/{}", new_path, *new_step);
+            match (old_child, new_child) {
+                (ElementElementChild(old_element), ElementElementChild(new_element)) => {
+                    self.compare(&old_element, old_child_path.as_slice(), &new_element, new_child_path.as_slice())
+                },
+                _ => self.edits.push(UpdateTextEdit(old_child_path, text(&old_child, self.options),
+                                                    text(&new_child, self.options)))
+            }
+        }
+        for (i, &(_, ref step, _)) in old_children.iter().enumerate() {
+            if !old_matched[i] {
+                self.deleted.push((format!("{}/{}", old_path, *step), Some(old_signatures[i])));
+            }
+        }
+        for (j, &(child, ref step, position)) in new_children.iter().enumerate() {
+            if !new_matched[j] {
+                self.inserted.push((format!("{}/{}", new_path, *step), Some(new_signatures[j]),
+                                    child.to_string(), format!("{}/node()[{}]", new_path, position)));
+            }
+        }
+    }
+
+    fn compare_attributes(&mut self, old: &BorrowedElement, path: &str, new: &BorrowedElement) {
+        let old_attributes = attributes(old, self.options);
+        let new_attributes = attributes(new, self.options);
+        for &(ref key, ref name, ref value) in old_attributes.iter() {
+            match new_attributes.iter().find(|&&(ref new_key, _, _)| new_key == key) {
+                None => self.edits.push(AttributeEdit(path.to_owned(), name.clone(), Some(value.clone()), None)),
+                Some(&(_, _, ref new_value)) if new_value != value => {
+                    self.edits.push(AttributeEdit(path.to_owned(), name.clone(), Some(value.clone()),
+                                                  Some(new_value.clone())))
+                },
+                Some(_) => {}
+            }
+        }
+        for &(ref key, ref name, ref value) in new_attributes.iter() {
+            if !old_attributes.iter().any(|&(ref old_key, _, _)| old_key == key) {
+                self.edits.push(AttributeEdit(path.to_owned(), name.clone(), None, Some(value.clone())));
+            }
+        }
+        if !self.options.ignore_attribute_order {
+            let common = |attributes: &~[(~str, ~str, ~str)], others: &~[(~str, ~str, ~str)]| {
+                attributes.iter().map(|&(ref key, _, _)| key.clone()).filter(|key| {
+                    others.iter().any(|&(ref other, _, _)| other == key)
+                }).to_owned_vec()
+            };
+            if common(&old_attributes, &new_attributes) != common(&new_attributes, &old_attributes) {
+                self.edits.push(AttributeOrderEdit(path.to_owned()));
+            }
+        }
+    }
+
+    // Pairs deleted and inserted nodes with equal signatures into moves
+    fn finish(&mut self) -> ~[Edit] {
+        let mut edits = std::util::replace(&mut self.edits, ~[]);
+        let (deleted, inserted) = (&self.deleted, &self.inserted);
+        let mut used = std::vec::from_elem(inserted.len(), false);
+        let mut deletes = ~[];
+        for &(ref old_path, old_signature) in deleted.iter() {
+            let found = inserted.iter().enumerate().position(|(j, &(_, new_signature, _, _))| {
+                !used[j] && old_signature.is_some() && new_signature == old_signature
+            });
+            match found {
+                Some(j) => {
+                    used[j] = true;
+                    let (_, _, _, ref position) = inserted[j];
+                    edits.push(MoveEdit(old_path.clone(), position.clone()));
+                },
+                None => deletes.push(DeleteEdit(old_path.clone()))
+            }
+        }
+        edits.push_all_move(deletes);
+        for (j, &(ref path, _, ref content, _)) in inserted.iter().enumerate() {
+            if !used[j] {
+                edits.push(InsertEdit(path.clone(), content.clone()));
+            }
+        }
+        edits
+    }
+
+    // Identifies subtrees which are equal under the options. Signatures of
+    // elements are hashed from those of their children and computed once,
+    // equal hashes being taken as equal subtrees.
+    fn signature(&mut self, child: &ElementChild) -> u64 {
+        let options = self.options;
+        match *child {
+            ElementElementChild(ref element) => {
+                let id = element.node as *ffi::xmlNode as uint;
+                match self.signatures.find(&id) {
+                    Some(&signature) => return signature,
+                    None => {}
+                }
+                let mut attributes = attributes(element, options);
+                if options.ignore_attribute_order {
+                    attributes.sort_by(|&(ref a, _, _), &(ref b, _, _)| a.cmp(b));
+                }
+                let mut description = format!("<{}", key(child, options));
+                for &(ref key, _, ref value) in attributes.iter() {
+                    description.push_str(format!(" {}{}:{}", key, value.len(), *value));
+                }
+                description.push_char('>');
+                for &(child, _, _) in children(element, options).iter() {
+                    description.push_str(format!("{:x} ", self.signature(&child)));
+                }
+                let signature = description.hash();
+                self.signatures.insert(id, signature);
+                signature
+            },
+            TextElementChild(_) | CDataElementChild(_) => format!("T{}", text(child, options)).hash(),
+            CommentElementChild(_) => format!("C{}", text(child, options)).hash()
+        }
+    }
+}
+
+// Children taking part in comparison with their path steps and positions
+// among all child nodes
+fn children<'r>(element: &BorrowedElement<'r>, options: &DiffOptions) -> ~[(ElementChild<'r>, ~str, uint)] {
+    let mut counts: HashMap<~str, uint> = HashMap::new();
+    let mut result = ~[];
+    let mut position = 0;
+    for child in element.children_iter() {
+        position += 1;
+        let name = match child {
+            ElementElementChild(element) => qualified_name(&element),
+            TextElementChild(_) | CDataElementChild(_) => ~"text()",
+            CommentElementChild(_) => ~"comment()"
+        };
+        let index = {
+            let count = counts.find_or_insert(name.clone(), 0);
+            *count += 1;
+            *count
+        };
+        let skip = match child {
+            TextElementChild(_) | CDataElementChild(_) => {
+                options.ignore_whitespace && text(&child, options).is_empty()
+            },
+            CommentElementChild(_) => options.ignore_comments,
+            ElementElementChild(_) => false
+        };
+        if !skip {
+            result.push((child, format!("{}[{}]", name, index), position));
+        }
+    }
+    result
+}
+
+// Identifies nodes which can be matched with each other
+fn key(child: &ElementChild, options: &DiffOptions) -> ~str {
+    match *child {
+        ElementElementChild(ref element) => {
+            let (prefix, href) = match element.namespace() {
+                Some(ns) => (ns.prefix().unwrap_or(~""), ns.href()),
+                None => (~"", ~"")
+            };
+            let prefix = if options.ignore_prefixes {~""} else {prefix};
+            format!("{}|{}|{}", prefix, href, element.name())
+        },
+        TextElementChild(_) | CDataElementChild(_) => ~"#text",
+        CommentElementChild(_) => ~"#comment"
+    }
+}
+
+// Attributes as key, qualified name and value
+fn attributes(element: &BorrowedElement, options: &DiffOptions) -> ~[(~str, ~str, ~str)] {
+    element.attribute_iter().map(|attr| {
+        let (prefix, href) = match attr.namespace() {
+            Some(ns) => (ns.prefix(), ns.href()),
+            None => (None, ~"")
+        };
+        let name = match prefix {
+            Some(ref prefix) => format!("{}:{}", *prefix, attr.name()),
+            None => attr.name()
+        };
+        let key_prefix = if options.ignore_prefixes {~""} else {prefix.unwrap_or(~"")};
+        (format!("{}|{}|{}", key_prefix, href, attr.name()), name, attr.value())
+    }).to_owned_vec()
+}
+
+fn text(child: &ElementChild, options: &DiffOptions) -> ~str {
+    let text = match *child {
+        TextElementChild(ref text) => text.content(),
+        CDataElementChild(ref cdata) => cdata.content(),
+        CommentElementChild(ref comment) => comment.comment(),
+        ElementElementChild(ref element) => element.text_content()
+    };
+    if options.ignore_whitespace {
+        text.trim().to_owned()
+    } else {
+        text
+    }
+}
+
+fn qualified_name(element: &BorrowedElement) -> ~str {
+    match element.namespace().and_then(|ns| ns.prefix()) {
+        Some(prefix) => format!("{}:{}", prefix, element.name()),
+        None => element.name()
+    }
+}
+
+fn element_path(element: &BorrowedElement) -> ~str {
+    let step = |element: &BorrowedElement| {
+        let name = qualified_name(element);
+        let mut index = 1;
+        match element.parent() {
+            Some(parent) => {
+                for sibling in parent.child_elements() {
+                    if sibling == *element {
+                        break;
+                    }
+                    if qualified_name(&sibling) == name {
+                        index += 1;
+                    }
+                }
+            },
+            None => {}
+        }
+        format!("{}[{}]", name, index)
+    };
+    let mut steps = element.ancestors().map(|ancestor| step(&ancestor)).to_owned_vec();
+    steps.reverse();
+    steps.push(step(element));
+    format!("/{}", steps.connect("/"))
+}
+
+// Number of cells above which longest common subsequence is approximated
+static LCS_LIMIT: uint = 1 << 20;
+
+// Longest common subsequence, as pairs of indices. Long sequences are
+// matched in linear time instead, which may miss some common items.
+fn lcs<T: Eq + Hash + Clone>(a: &[T], b: &[T]) -> ~[(uint, uint)] {
+    let (n, m) = (a.len(), b.len());
+    if (n + 1) * (m + 1) > LCS_LIMIT {
+        return linear_matches(a, b);
+    }
+    let mut table = std::vec::from_elem((n + 1) * (m + 1), 0u);
+    for i in range(1, n + 1) {
+        for j in range(1, m + 1) {
+            table[i * (m + 1) + j] = if a[i - 1] == b[j - 1] {
+                table[(i - 1) * (m + 1) + j - 1] + 1
+            } else {
+                std::num::max(table[(i - 1) * (m + 1) + j], table[i * (m + 1) + j - 1])
+            };
+        }
+    }
+    let mut pairs = ~[];
+    let (mut i, mut j) = (n, m);
+    while i > 0 && j > 0 {
+        if a[i - 1] == b[j - 1] {
+            pairs.push((i - 1, j - 1));
+            i -= 1;
+            j -= 1;
+        } else if table[(i - 1) * (m + 1) + j] >= table[i * (m + 1) + j - 1] {
+            i -= 1;
+        } else {
+            j -= 1;
+        }
+    }
+    pairs.reverse();
+    pairs
+}
+
+// Keeps common prefix and suffix, matching the rest greedily: each item
+// with the first equal one after the previous match
+fn linear_matches<T: Eq + Hash + Clone>(a: &[T], b: &[T]) -> ~[(uint, uint)] {
+    let (n, m) = (a.len(), b.len());
+    let mut prefix = 0;
+    while prefix < n && prefix < m && a[prefix] == b[prefix] {
+        prefix += 1;
+    }
+    let mut suffix = 0;
+    while suffix < n - prefix && suffix < m - prefix && a[n - 1 - suffix] == b[m - 1 - suffix] {
+        suffix += 1;
+    }
+    let mut pairs = range(0, prefix).map(|k| (k, k)).to_owned_vec();
+    // Positions of remaining items of b, in descending order
+    let mut positions: HashMap<T, ~[uint]> = HashMap::new();
+    for j in range(prefix, m - suffix).invert() {
+        positions.find_or_insert(b[j].clone(), ~[]).push(j);
+    }
+    let mut start = prefix;
+    for i in range(prefix, n - suffix) {
+        match positions.find_mut(&a[i]) {
+            Some(indices) => {
+                while indices.last_opt().map_default(false, |&j| j < start) {
+                    indices.pop();
+                }
+                match indices.pop_opt() {
+                    Some(j) => {
+                        pairs.push((i, j));
+                        start = j + 1;
+                    },
+                    None => {}
+                }
+            },
+            None => {}
+        }
+    }
+    for k in range(0, suffix) {
+        pairs.push((n - suffix + k, m - suffix + k));
+    }
+    pairs
+}
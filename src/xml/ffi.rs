@@ -20,7 +20,7 @@
  * THE SOFTWARE.
  */
 
-use std::libc::{c_char, c_int, c_long, c_uchar, c_ushort, c_void};
+use std::libc::{c_char, c_double, c_int, c_long, c_uchar, c_uint, c_ulong, c_ushort, c_void};
 
 pub type xmlChar = c_uchar;
 
@@ -61,11 +61,10 @@ pub struct xmlAttr {
     prev: *xmlAttr,
     doc: *xmlDoc,
     ns: *xmlNs,
-    atype: xmlAttributeType,
+    atype: c_int, // xmlAttributeType or 0 if not determined
     psvi: *c_void
 }
 
-#[allow(dead_code)]
 pub struct xmlAttribute {
     _private: *c_void,
     _type: xmlElementType, // AttributeDecl
@@ -85,7 +84,6 @@ pub struct xmlAttribute {
     elem: *xmlChar
 }
 
-#[allow(dead_code)]
 #[repr(C)]
 pub enum xmlAttributeDefault {
     None = 1,
@@ -154,7 +152,6 @@ pub struct xmlDtd {
     pentities: *c_void
 }
 
-#[allow(dead_code)]
 pub struct xmlElement {
     _private: *c_void,
     _type: xmlElementType, // ElementDecl
@@ -166,23 +163,21 @@ pub struct xmlElement {
     prev: *xmlNode,
     doc: *xmlDoc,
     etype: xmlElementTypeVal,
-    content: xmlElementContent,
+    content: *xmlElementContent,
     attributes: *xmlAttribute,
     prefix: *xmlChar,
     contModel: *c_void
 }
 
-#[allow(dead_code)]
 #[repr(C)]
 pub enum xmlElementTypeVal {
-    Undefined,
-    Empty,
-    Any,
-    Mixed,
-    ElementType
+    Undefined = 0,
+    Empty = 1,
+    Any = 2,
+    Mixed = 3,
+    ElementType = 4
 }
 
-#[allow(dead_code)]
 pub struct xmlElementContent {
     _type: xmlElementContentType,
     ocur: xmlElementContentOccur,
@@ -193,25 +188,22 @@ pub struct xmlElementContent {
     prefix: *xmlChar
 }
 
-#[allow(dead_code)]
 #[repr(C)]
 pub enum xmlElementContentType {
-    PCData,
-    ElementContent,
-    Seq,
-    Or
+    PCData = 1,
+    ElementContent = 2,
+    Seq = 3,
+    Or = 4
 }
 
-#[allow(dead_code)]
 #[repr(C)]
 pub enum xmlElementContentOccur {
-    Once,
-    Opt,
-    Mult,
-    Plus
+    Once = 1,
+    Opt = 2,
+    Mult = 3,
+    Plus = 4
 }
 
-#[allow(dead_code)]
 pub struct xmlEntity {
     _private: *c_void,
     _type: xmlElementType, // EntityDecl
@@ -224,7 +216,8 @@ pub struct xmlEntity {
     doc: *xmlDoc,
     orig: *xmlChar,
     content: *xmlChar,
-    length: xmlEntityType,
+    length: c_int,
+    etype: xmlEntityType,
     externalID: *xmlChar,
     systemID: *xmlChar,
     nexte: *c_void,
@@ -233,7 +226,6 @@ pub struct xmlEntity {
     checked: c_int
 }
 
-#[allow(dead_code)]
 #[repr(C)]
 pub enum xmlEntityType {
     InternalGeneralEntity = 1,
@@ -244,7 +236,6 @@ pub enum xmlEntityType {
     InternalPredefinedEntity = 6
 }
 
-#[allow(dead_code)]
 pub struct xmlEnumeration {
     next: *xmlEnumeration,
     name: *xmlChar
@@ -278,13 +269,105 @@ pub struct xmlNs {
     context: *xmlDoc
 }
 
+pub struct xmlError {
+    domain: c_int,
+    code: c_int,
+    message: *c_char,
+    level: c_int,
+    file: *c_char,
+    line: c_int,
+    str1: *c_char,
+    str2: *c_char,
+    str3: *c_char,
+    int1: c_int,
+    int2: c_int,
+    ctxt: *c_void,
+    node: *c_void
+}
+
+pub struct z_stream {
+    next_in: *u8,
+    avail_in: c_uint,
+    total_in: c_ulong,
+    next_out: *mut u8,
+    avail_out: c_uint,
+    total_out: c_ulong,
+    msg: *c_char,
+    state: *c_void,
+    zalloc: *c_void,
+    zfree: *c_void,
+    opaque: *c_void,
+    data_type: c_int,
+    adler: c_ulong,
+    reserved: c_ulong
+}
+
+pub struct xmlNodeSet {
+    nodeNr: c_int,
+    nodeMax: c_int,
+    nodeTab: **xmlNode
+}
+
+pub struct xmlXPathObject {
+    otype: c_int, // XPATH_NODESET is 1
+    nodesetval: *xmlNodeSet,
+    boolval: c_int,
+    floatval: c_double,
+    stringval: *xmlChar,
+    user: *c_void,
+    index: c_int,
+    user2: *c_void,
+    index2: c_int
+}
+
+// Only the leading fields, it is always allocated by libxml2
+pub struct xmlParserInput {
+    buf: *xmlParserInputBuffer,
+    filename: *c_char
+}
+
 enum xmlSaveCtxt {}
+pub enum xmlXPathContext {}
+pub enum xmlParserCtxt {}
+pub enum xmlParserInputBuffer {}
+pub enum xmlBuffer {}
+pub enum xmlSchema {}
+pub enum xmlSchemaParserCtxt {}
+pub enum xmlSchemaValidCtxt {}
+pub enum xmlRelaxNG {}
+pub enum xmlRelaxNGParserCtxt {}
+pub enum xmlRelaxNGValidCtxt {}
+pub enum xmlValidCtxt {}
 
 #[link(name = "xml2")]
 extern "C" {
     pub fn xmlCheckVersion(version: c_int);
+    pub fn xmlInitParser();
+
+    // Memory API
+    pub static xmlFree: extern "C" fn (ptr: *c_void);
+
+    // Error API
+    pub fn xmlGetLastError() -> *xmlError;
+    pub fn xmlResetLastError();
+    pub fn xmlSetStructuredErrorFunc(context: *c_void,
+                                     handler: extern "C" fn (context: *c_void, error: *xmlError));
 
     // Parser API
+    pub fn xmlNewParserCtxt() -> *xmlParserCtxt;
+    pub fn xmlFreeParserCtxt(ctxt: *xmlParserCtxt);
+    pub fn xmlCtxtGetLastError(ctxt: *xmlParserCtxt) -> *xmlError;
+    pub fn xmlCtxtReadFile(ctxt: *xmlParserCtxt,
+                           filename: *c_char,
+                           encoding: *c_char,
+                           options: c_int) -> *xmlDoc;
+    pub fn xmlReadMemory(buffer: *c_char,
+                         size: c_int,
+                         url: *c_char,
+                         encoding: *c_char,
+                         options: c_int) -> *xmlDoc;
+    pub fn xmlParseInNodeContext(node: *xmlNode, data: *c_char, datalen: c_int, options: c_int,
+                                 lst: *mut *xmlNode) -> c_int;
     pub fn xmlReadIO(ioread: extern "C" fn (context: *mut c_void, buffer: *mut c_char, len: c_int) -> c_int,
                      ioclose: extern "C" fn (context: *mut c_void) -> c_int,
                      context: *mut c_void,
@@ -292,12 +375,110 @@ extern "C" {
                      encoding: *c_char,
                      options: c_int) -> *xmlDoc;
 
+    // Input API
+    pub fn xmlGetExternalEntityLoader() -> extern "C" fn (url: *c_char, id: *c_char, ctxt: *xmlParserCtxt) -> *xmlParserInput;
+    pub fn xmlSetExternalEntityLoader(loader: extern "C" fn (url: *c_char, id: *c_char, ctxt: *xmlParserCtxt) -> *xmlParserInput);
+    pub fn xmlParserInputBufferCreateMem(mem: *c_char, size: c_int, encoding: c_int) -> *xmlParserInputBuffer;
+    pub fn xmlFreeParserInputBuffer(buf: *xmlParserInputBuffer);
+    pub fn xmlNewIOInputStream(ctxt: *xmlParserCtxt, buf: *xmlParserInputBuffer, encoding: c_int) -> *xmlParserInput;
+    pub fn xmlRegisterInputCallbacks(match_func: extern "C" fn (filename: *c_char) -> c_int,
+                                     open_func: extern "C" fn (filename: *c_char) -> *c_void,
+                                     read_func: extern "C" fn (context: *c_void, buffer: *mut c_char, len: c_int) -> c_int,
+                                     close_func: extern "C" fn (context: *c_void) -> c_int) -> c_int;
+    pub fn xmlBuildURI(uri: *xmlChar, base: *xmlChar) -> *xmlChar;
+
+    // Catalog API
+    pub fn xmlCatalogAddLocal(catalogs: *c_void, url: *xmlChar) -> *c_void;
+    pub fn xmlCatalogLocalResolve(catalogs: *c_void, pub_id: *xmlChar, sys_id: *xmlChar) -> *xmlChar;
+    pub fn xmlCatalogLocalResolveURI(catalogs: *c_void, uri: *xmlChar) -> *xmlChar;
+    pub fn xmlCatalogFreeLocal(catalogs: *c_void);
+
+    // XPath API
+    pub fn xmlXPathNewContext(doc: *xmlDoc) -> *xmlXPathContext;
+    pub fn xmlXPathFreeContext(ctxt: *xmlXPathContext);
+    pub fn xmlXPathRegisterNs(ctxt: *xmlXPathContext, prefix: *xmlChar, href: *xmlChar) -> c_int;
+    pub fn xmlXPathEvalExpression(expr: *xmlChar, ctxt: *xmlXPathContext) -> *xmlXPathObject;
+    pub fn xmlXPathFreeObject(obj: *xmlXPathObject);
+
+    // String API
+    pub fn xmlStrEqual(str1: *xmlChar, str2: *xmlChar) -> c_int;
+    pub fn xmlStrdup(cur: *xmlChar) -> *xmlChar;
+    pub fn xmlValidateNCName(value: *xmlChar, space: c_int) -> c_int;
+
     // Tree API
+    pub fn xmlSearchNs(doc: *xmlDoc, node: *xmlNode, prefix: *xmlChar) -> *xmlNs;
+    pub fn xmlSearchNsByHref(doc: *xmlDoc, node: *xmlNode, href: *xmlChar) -> *xmlNs;
+    pub fn xmlGetNsList(doc: *xmlDoc, node: *xmlNode) -> **xmlNs;
+    pub fn xmlNewNs(node: *xmlNode, href: *xmlChar, prefix: *xmlChar) -> *xmlNs;
+    pub fn xmlFreeNs(ns: *xmlNs);
+    pub fn xmlReconciliateNs(doc: *xmlDoc, tree: *xmlNode) -> c_int;
+    pub fn xmlNewDoc(version: *xmlChar) -> *xmlDoc;
+    pub fn xmlNewDocNode(doc: *xmlDoc, ns: *xmlNs, name: *xmlChar, content: *xmlChar) -> *xmlNode;
+    pub fn xmlDocSetRootElement(doc: *xmlDoc, root: *xmlNode) -> *xmlNode;
+    pub fn xmlSetNs(node: *xmlNode, ns: *xmlNs);
+    pub fn xmlNewProp(node: *xmlNode, name: *xmlChar, value: *xmlChar) -> *xmlAttr;
+    pub fn xmlSetNsProp(node: *xmlNode, ns: *xmlNs, name: *xmlChar, value: *xmlChar) -> *xmlAttr;
+    pub fn xmlNodeSetName(node: *xmlNode, name: *xmlChar);
+    pub fn xmlNodeSetContent(node: *xmlNode, content: *xmlChar);
+    pub fn xmlNodeGetContent(node: *xmlNode) -> *xmlChar;
+    pub fn xmlNodeAddContent(node: *xmlNode, content: *xmlChar);
+    pub fn xmlEncodeSpecialChars(doc: *xmlDoc, input: *xmlChar) -> *xmlChar;
+    pub fn xmlGetLineNo(node: *xmlNode) -> c_long;
+    pub fn xmlGetID(doc: *xmlDoc, id: *xmlChar) -> *xmlAttr;
+    pub fn xmlGetDtdQAttrDesc(dtd: *xmlDtd, elem: *xmlChar, name: *xmlChar, prefix: *xmlChar) -> *xmlAttribute;
     pub fn xmlDocGetRootElement(doc: *xmlDoc) -> *xmlNode;
     pub fn xmlFreeDoc(doc: *xmlDoc);
+    pub fn xmlCopyDoc(doc: *xmlDoc, recursive: c_int) -> *xmlDoc;
+    pub fn xmlDocCopyNode(node: *xmlNode, doc: *xmlDoc, extended: c_int) -> *xmlNode;
+    pub fn xmlAddChild(parent: *xmlNode, cur: *xmlNode) -> *xmlNode;
+    pub fn xmlAddPrevSibling(cur: *xmlNode, elem: *xmlNode) -> *xmlNode;
+    pub fn xmlAddNextSibling(cur: *xmlNode, elem: *xmlNode) -> *xmlNode;
+    pub fn xmlReplaceNode(old: *xmlNode, cur: *xmlNode) -> *xmlNode;
+    pub fn xmlUnlinkNode(cur: *xmlNode);
+    pub fn xmlHasNsProp(node: *xmlNode, name: *xmlChar, href: *xmlChar) -> *xmlAttr;
+    pub fn xmlRemoveProp(attr: *xmlAttr) -> c_int;
+    pub fn xmlFreeNode(node: *xmlNode);
+    pub fn xmlFreeNodeList(node: *xmlNode);
+    pub fn xmlIsBlankNode(node: *xmlNode) -> c_int;
 
     // XML Save API
+    pub fn xmlBufferCreate() -> *xmlBuffer;
+    pub fn xmlBufferFree(buf: *xmlBuffer);
+    pub fn xmlBufferContent(buf: *xmlBuffer) -> *xmlChar;
+    pub fn xmlNodeDump(buf: *xmlBuffer, doc: *xmlDoc, node: *xmlNode, level: c_int, format: c_int) -> c_int;
+    pub fn xmlDocDumpFormatMemoryEnc(doc: *xmlDoc,
+                                     mem: *mut *xmlChar,
+                                     size: *mut c_int,
+                                     encoding: *c_char,
+                                     format: c_int);
+    pub fn xmlSaveFileEnc(filename: *c_char, doc: *xmlDoc, encoding: *c_char) -> c_int;
     pub fn xmlSaveClose(ctx: *xmlSaveCtxt) -> c_int;
+    // Validation API
+    pub fn xmlSchemaNewParserCtxt(url: *c_char) -> *xmlSchemaParserCtxt;
+    pub fn xmlSchemaNewDocParserCtxt(doc: *xmlDoc) -> *xmlSchemaParserCtxt;
+    pub fn xmlSchemaParse(ctxt: *xmlSchemaParserCtxt) -> *xmlSchema;
+    pub fn xmlSchemaFreeParserCtxt(ctxt: *xmlSchemaParserCtxt);
+    pub fn xmlSchemaFree(schema: *xmlSchema);
+    pub fn xmlSchemaNewValidCtxt(schema: *xmlSchema) -> *xmlSchemaValidCtxt;
+    pub fn xmlSchemaValidateDoc(ctxt: *xmlSchemaValidCtxt, doc: *xmlDoc) -> c_int;
+    pub fn xmlSchemaFreeValidCtxt(ctxt: *xmlSchemaValidCtxt);
+    pub fn xmlRelaxNGNewParserCtxt(url: *c_char) -> *xmlRelaxNGParserCtxt;
+    pub fn xmlRelaxNGParse(ctxt: *xmlRelaxNGParserCtxt) -> *xmlRelaxNG;
+    pub fn xmlRelaxNGFreeParserCtxt(ctxt: *xmlRelaxNGParserCtxt);
+    pub fn xmlRelaxNGFree(schema: *xmlRelaxNG);
+    pub fn xmlRelaxNGNewValidCtxt(schema: *xmlRelaxNG) -> *xmlRelaxNGValidCtxt;
+    pub fn xmlRelaxNGValidateDoc(ctxt: *xmlRelaxNGValidCtxt, doc: *xmlDoc) -> c_int;
+    pub fn xmlRelaxNGFreeValidCtxt(ctxt: *xmlRelaxNGValidCtxt);
+    pub fn xmlParseDTD(external_id: *xmlChar, system_id: *xmlChar) -> *xmlDtd;
+    pub fn xmlFreeDtd(dtd: *xmlDtd);
+    pub fn xmlNewValidCtxt() -> *xmlValidCtxt;
+    pub fn xmlFreeValidCtxt(ctxt: *xmlValidCtxt);
+    pub fn xmlValidateDtd(ctxt: *xmlValidCtxt, doc: *xmlDoc, dtd: *xmlDtd) -> c_int;
+
+    // Canonicalization API
+    pub fn xmlC14NDocDumpMemory(doc: *xmlDoc, nodes: *xmlNodeSet, mode: c_int, inclusive_ns_prefixes: **xmlChar,
+                                with_comments: c_int, doc_txt_ptr: *mut *xmlChar) -> c_int;
+
     pub fn xmlSaveDoc(ctx: *xmlSaveCtxt, doc: *xmlDoc) -> c_long;
     pub fn xmlSaveToIO(iowrite: extern "C" fn (context: *mut c_void, buffer: *c_char, len: c_int) -> c_int,
                        ioclose: extern "C" fn (context: *mut c_void) -> c_int,
@@ -306,4 +487,25 @@ extern "C" {
                        options: c_int) -> *xmlSaveCtxt;
 }
 
+#[link(name = "z")]
+extern "C" {
+    pub fn zlibVersion() -> *c_char;
+    pub fn inflateInit2_(strm: *mut z_stream, windowBits: c_int, version: *c_char, stream_size: c_int) -> c_int;
+    pub fn inflate(strm: *mut z_stream, flush: c_int) -> c_int;
+    pub fn inflateReset(strm: *mut z_stream) -> c_int;
+    pub fn inflateEnd(strm: *mut z_stream) -> c_int;
+    pub fn deflateInit2_(strm: *mut z_stream, level: c_int, method: c_int, windowBits: c_int,
+                         memLevel: c_int, strategy: c_int, version: *c_char, stream_size: c_int) -> c_int;
+    pub fn deflate(strm: *mut z_stream, flush: c_int) -> c_int;
+    pub fn deflateEnd(strm: *mut z_stream) -> c_int;
+}
+
 pub static xmlVersion : c_int = 20901;
+
+pub static Z_OK: c_int = 0;
+pub static Z_STREAM_END: c_int = 1;
+pub static Z_BUF_ERROR: c_int = -5;
+pub static Z_NO_FLUSH: c_int = 0;
+pub static Z_SYNC_FLUSH: c_int = 2;
+pub static Z_FINISH: c_int = 4;
+pub static Z_DEFLATED: c_int = 8;
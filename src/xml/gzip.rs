@@ -0,0 +1,334 @@
+/*
+ * Copyright (C) 2014 Maciej Piechotka
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+ * THE SOFTWARE.
+ */
+
+// Gzip compressed streams, implemented with zlib which libxml2 links anyway.
+
+use std::io::{IoError,OtherIoError,io_error};
+use std::libc::{c_int, c_uint};
+use ffi;
+
+static BUFFER_SIZE: uint = 8192;
+// Readers may return no data without being at the end, but not forever.
+// Reading fails after that many empty reads in a row.
+static MAX_EMPTY_READS: uint = 64;
+
+enum ReaderState {
+    Detecting,
+    Plain,
+    Compressed,
+    Finished
+}
+
+/**
+ * Reader decompressing gzip input and passing other input unchanged.
+ */
+pub struct DetectingReader<'t> {
+    priv reader: &'t mut Reader,
+    priv state: ReaderState,
+    priv stream: ~ffi::z_stream,
+    priv input: ~[u8],
+    priv start: uint,
+    priv end: uint,
+    // Error was raised as the reader stopped making progress
+    priv stalled: bool
+}
+
+/**
+ * Writer compressing output with gzip.
+ */
+pub struct CompressingWriter<'t> {
+    priv writer: &'t mut Writer,
+    priv stream: ~ffi::z_stream,
+    priv output: ~[u8],
+    priv written: uint,
+    priv finished: bool
+}
+
+impl<'t> DetectingReader<'t> {
+    pub fn new(reader: &'t mut Reader) -> DetectingReader<'t> {
+        DetectingReader {
+            reader: reader,
+            state: Detecting,
+            stream: ~new_stream(),
+            input: std::vec::from_elem(BUFFER_SIZE, 0u8),
+            start: 0,
+            end: 0,
+            stalled: false
+        }
+    }
+
+    /**
+     * Check if the input was compressed. It is known only after first read.
+     */
+    pub fn is_compressed(&self) -> bool {
+        match self.state {
+            Plain | Detecting => false,
+            Compressed | Finished => true
+        }
+    }
+
+    // Reads more input into buffer, returning false at the end of input.
+    // If the reader stops making progress, raises an error.
+    fn fill(&mut self) -> bool {
+        if self.start > 0 {
+            let len = self.end - self.start;
+            for i in range(0, len) {
+                self.input[i] = self.input[self.start + i];
+            }
+            self.start = 0;
+            self.end = len;
+        }
+        if self.end == self.input.len() {
+            return true;
+        }
+        for _ in range(0, MAX_EMPTY_READS) {
+            match self.reader.read(self.input.mut_slice_from(self.end)) {
+                Some(0) => {},
+                Some(n) => {
+                    self.end += n;
+                    return true;
+                },
+                None => return false
+            }
+        }
+        self.stalled = true;
+        io_error::cond.raise(IoError {
+            kind: OtherIoError,
+            desc: "Reader returned no data repeatedly",
+            detail: None
+        });
+        false
+    }
+
+    fn detect(&mut self) {
+        while self.end < 2 && self.fill() {}
+        if self.end >= 2 && self.input[0] == 0x1f && self.input[1] == 0x8b {
+            let ret = unsafe {
+                ffi::inflateInit2_(&mut *self.stream, 15 + 16 /* gzip only */,
+                                   ffi::zlibVersion(), std::mem::size_of::<ffi::z_stream>() as c_int)
+            };
+            if ret != ffi::Z_OK {
+                fail!("Failed to initialize zlib");
+            }
+            self.state = Compressed;
+        } else {
+            self.state = Plain;
+        }
+    }
+
+    fn inflate(&mut self, buf: &mut [u8]) -> Option<uint> {
+        // Nothing would be ever produced
+        if buf.is_empty() {
+            return Some(0);
+        }
+        loop {
+            if self.start == self.end && !self.fill() {
+                self.state = Finished;
+                if !self.stalled {
+                    io_error::cond.raise(corrupted("Unexpected end of compressed input"));
+                }
+                return None;
+            }
+            let (ret, produced) = unsafe {
+                self.stream.next_in = self.input.as_ptr().offset(self.start as int);
+                self.stream.avail_in = (self.end - self.start) as c_uint;
+                self.stream.next_out = buf.as_mut_ptr();
+                self.stream.avail_out = buf.len() as c_uint;
+                let ret = ffi::inflate(&mut *self.stream, ffi::Z_NO_FLUSH);
+                self.start = self.end - self.stream.avail_in as uint;
+                (ret, buf.len() - self.stream.avail_out as uint)
+            };
+            if ret == ffi::Z_STREAM_END {
+                // Further gzip members are part of the same input
+                if self.start == self.end && !self.fill() {
+                    self.state = Finished;
+                    return if produced > 0 {Some(produced)} else {None};
+                }
+                unsafe {ffi::inflateReset(&mut *self.stream)};
+            } else if ret != ffi::Z_OK && ret != ffi::Z_BUF_ERROR {
+                self.state = Finished;
+                io_error::cond.raise(corrupted("Corrupted compressed input"));
+                return None;
+            }
+            if produced > 0 {
+                return Some(produced);
+            }
+        }
+    }
+}
+
+impl<'t> Reader for DetectingReader<'t> {
+    fn read(&mut self, buf: &mut [u8]) -> Option<uint> {
+        match self.state {
+            Detecting => {
+                self.detect();
+                self.read(buf)
+            },
+            Plain => {
+                if self.start < self.end {
+                    let len = std::num::min(buf.len(), self.end - self.start);
+                    std::vec::bytes::copy_memory(buf.mut_slice_to(len), self.input.slice(self.start, self.start + len));
+                    self.start += len;
+                    Some(len)
+                } else {
+                    self.reader.read(buf)
+                }
+            },
+            Compressed => self.inflate(buf),
+            Finished => None
+        }
+    }
+
+    fn eof(&mut self) -> bool {
+        match self.state {
+            Detecting | Compressed => false,
+            Plain => self.start == self.end && self.reader.eof(),
+            Finished => true
+        }
+    }
+}
+
+#[unsafe_destructor]
+impl<'t> Drop for DetectingReader<'t> {
+    fn drop(&mut self) {
+        match self.state {
+            Compressed | Finished => unsafe {
+                ffi::inflateEnd(&mut *self.stream);
+            },
+            _ => {}
+        }
+    }
+}
+
+impl<'t> CompressingWriter<'t> {
+    /**
+     * Create writer compressing with given level, from 1 to 9.
+     */
+    pub fn new(writer: &'t mut Writer, level: uint) -> CompressingWriter<'t> {
+        let mut stream = ~new_stream();
+        let ret = unsafe {
+            ffi::deflateInit2_(&mut *stream, std::num::min(level, 9) as c_int, ffi::Z_DEFLATED,
+                               15 + 16 /* gzip */, 8, 0,
+                               ffi::zlibVersion(), std::mem::size_of::<ffi::z_stream>() as c_int)
+        };
+        if ret != ffi::Z_OK {
+            fail!("Failed to initialize zlib");
+        }
+        CompressingWriter {
+            writer: writer,
+            stream: stream,
+            output: std::vec::from_elem(BUFFER_SIZE, 0u8),
+            written: 0,
+            finished: false
+        }
+    }
+
+    /**
+     * Write the end of compressed stream, returning the number of bytes
+     * written to the underlying writer.
+     */
+    pub fn finish(&mut self) -> uint {
+        if !self.finished {
+            self.finished = true;
+            self.deflate([], ffi::Z_FINISH);
+            self.writer.flush();
+        }
+        self.written
+    }
+
+    fn deflate(&mut self, buf: &[u8], flush: c_int) {
+        self.stream.next_in = buf.as_ptr();
+        self.stream.avail_in = buf.len() as c_uint;
+        loop {
+            self.stream.next_out = self.output.as_mut_ptr();
+            self.stream.avail_out = self.output.len() as c_uint;
+            let ret = unsafe {ffi::deflate(&mut *self.stream, flush)};
+            if ret != ffi::Z_OK && ret != ffi::Z_STREAM_END && ret != ffi::Z_BUF_ERROR {
+                io_error::cond.raise(corrupted("Failed to compress output"));
+                return;
+            }
+            let produced = self.output.len() - self.stream.avail_out as uint;
+            if produced > 0 {
+                self.writer.write(self.output.slice_to(produced));
+                self.written += produced;
+            }
+            let done = if flush == ffi::Z_FINISH {
+                ret == ffi::Z_STREAM_END
+            } else {
+                self.stream.avail_out != 0
+            };
+            if done {
+                break;
+            }
+        }
+    }
+}
+
+impl<'t> Writer for CompressingWriter<'t> {
+    fn write(&mut self, buf: &[u8]) {
+        self.deflate(buf, ffi::Z_NO_FLUSH);
+    }
+
+    fn flush(&mut self) {
+        if !self.finished {
+            self.deflate([], ffi::Z_SYNC_FLUSH);
+        }
+        self.writer.flush();
+    }
+}
+
+#[unsafe_destructor]
+impl<'t> Drop for CompressingWriter<'t> {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::deflateEnd(&mut *self.stream);
+        }
+    }
+}
+
+fn new_stream() -> ffi::z_stream {
+    use std::ptr::{null,mut_null};
+    ffi::z_stream {
+        next_in: null(),
+        avail_in: 0,
+        total_in: 0,
+        next_out: mut_null(),
+        avail_out: 0,
+        total_out: 0,
+        msg: null(),
+        state: null(),
+        zalloc: null(),
+        zfree: null(),
+        opaque: null(),
+        data_type: 0,
+        adler: 0,
+        reserved: 0
+    }
+}
+
+fn corrupted(desc: &'static str) -> IoError {
+    IoError {
+        kind: OtherIoError,
+        desc: desc,
+        detail: None
+    }
+}
@@ -24,10 +24,96 @@
 #[crate_id = "github.com/uzytkownik/xml-rs#xml:0.1"];
 #[crate_type = "lib"];
 #[license = "MIT"];
+#[feature(macro_rules)];
 
 extern mod extra;
 
 mod ffi;
+mod gzip;
+mod patch;
+pub mod catalog;
+pub mod codegen;
+pub mod diff;
+pub mod resolver;
+pub mod serialize;
+pub mod validation;
+pub mod xpath;
+
+// Nodes are identified by their address in the tree.
+macro_rules! node_identity(
+    ($name:ident, $field:ident, $ty:ty) => (
+        impl<'r> Eq for $name<'r> {
+            fn eq(&self, other: &$name<'r>) -> bool {
+                self.$field as *$ty == other.$field as *$ty
+            }
+        }
+
+        impl<'r> TotalEq for $name<'r> {
+            fn equals(&self, other: &$name<'r>) -> bool {
+                self.eq(other)
+            }
+        }
+
+        impl<'r> IterBytes for $name<'r> {
+            fn iter_bytes(&self, lsb0: bool, f: std::to_bytes::Cb) -> bool {
+                (self.$field as *$ty as uint).iter_bytes(lsb0, f)
+            }
+        }
+    )
+)
+
+// Nodes in tree are ordered in document order.
+macro_rules! node_order(
+    ($name:ident, $field:ident, $ty:ty) => (
+        impl<'r> Ord for $name<'r> {
+            fn lt(&self, other: &$name<'r>) -> bool {
+                self.cmp(other) == Less
+            }
+        }
+
+        impl<'r> TotalOrd for $name<'r> {
+            fn cmp(&self, other: &$name<'r>) -> Ordering {
+                document_order(self.$field as *$ty as *ffi::xmlNode, other.$field as *$ty as *ffi::xmlNode)
+            }
+        }
+    )
+)
+
+// Navigation shared by nodes which are children of elements.
+macro_rules! tree_node(
+    ($name:ident) => (
+        impl<'r> TreeNode<'r> for $name<'r> {
+            fn parent(&self) -> Option<BorrowedElement<'r>> {
+                node_parent(self.node)
+            }
+            fn next_sibling(&self) -> Option<ElementChild<'r>> {
+                node_next_sibling(self.node)
+            }
+            fn prev_sibling(&self) -> Option<ElementChild<'r>> {
+                node_prev_sibling(self.node)
+            }
+            fn next_element_sibling(&self) -> Option<BorrowedElement<'r>> {
+                node_sibling_element(self.node.next, |node| node.next)
+            }
+            fn prev_element_sibling(&self) -> Option<BorrowedElement<'r>> {
+                node_sibling_element(self.node.prev, |node| node.prev)
+            }
+            fn ancestors(&self) -> AncestorIterator<'r> {
+                AncestorIterator {
+                    cur: ptr_to_option(self.node.parent).map(|parent| unsafe {&*parent})
+                }
+            }
+            fn owner_document(&self) -> BorrowedDocument<'r> {
+                unsafe {
+                    BorrowedDocument {doc: &*self.node.doc}
+                }
+            }
+            fn line(&self) -> Option<uint> {
+                node_line(self.node)
+            }
+        }
+    )
+)
 
 /**
  * An XML node that contains text.
@@ -44,6 +130,21 @@ pub trait NamedNode {
     fn namespace<'r>(&'r self) -> Option<BorrowedNamespace<'r>>;
 }
 
+/**
+ * An XML node placed in the tree of elements. Nodes reached from it
+ * borrow the document, not the node.
+ */
+pub trait TreeNode<'r> {
+    fn parent(&self) -> Option<BorrowedElement<'r>>;
+    fn next_sibling(&self) -> Option<ElementChild<'r>>;
+    fn prev_sibling(&self) -> Option<ElementChild<'r>>;
+    fn next_element_sibling(&self) -> Option<BorrowedElement<'r>>;
+    fn prev_element_sibling(&self) -> Option<BorrowedElement<'r>>;
+    fn ancestors(&self) -> AncestorIterator<'r>;
+    fn owner_document(&self) -> BorrowedDocument<'r>;
+    fn line(&self) -> Option<uint>;
+}
+
 /**
  * An attribute of element.
  */
@@ -74,14 +175,83 @@ pub struct BorrowedComment<'r> {
 
 /**
  * An XML document.
+ *
+ * A document can be sent to other task, as it is owned by one task at a
+ * time. Borrowed nodes cannot be sent as they borrow the document.
  */
 pub struct Document {
     priv doc: *ffi::xmlDoc
 }
 
+/**
+ * A node which belongs to a document but is not yet placed in its tree.
+ * It is freed when dropped, unless it is inserted into the tree.
+ */
+pub struct DetachedNode<'r> {
+    priv node: *ffi::xmlNode,
+    priv doc: &'r ffi::xmlDoc
+}
+
+/**
+ * A document borrowed from one of its nodes.
+ */
+pub struct BorrowedDocument<'r> {
+    priv doc: &'r ffi::xmlDoc
+}
+
+/**
+ * A document type definition - either internal or external subset.
+ */
+pub struct BorrowedDtd<'r> {
+    priv dtd: &'r ffi::xmlDtd
+}
+
+/**
+ * An element declaration from DTD.
+ */
+pub struct BorrowedElementDecl<'r> {
+    priv decl: &'r ffi::xmlElement
+}
+
+/**
+ * An attribute declaration from DTD.
+ */
+pub struct BorrowedAttributeDecl<'r> {
+    priv decl: &'r ffi::xmlAttribute
+}
+
+/**
+ * An entity declaration from DTD.
+ */
+pub struct BorrowedEntityDecl<'r> {
+    priv decl: &'r ffi::xmlEntity
+}
+
+/**
+ * Iterator over element declarations of DTD
+ */
+pub struct ElementDeclIterator<'r> {
+    priv cur: Option<&'r ffi::xmlNode>
+}
+
+/**
+ * Iterator over attribute declarations of DTD
+ */
+pub struct AttributeDeclIterator<'r> {
+    priv cur: Option<&'r ffi::xmlNode>
+}
+
+/**
+ * Iterator over entity declarations of DTD
+ */
+pub struct EntityDeclIterator<'r> {
+    priv cur: Option<&'r ffi::xmlNode>
+}
+
 /**
  * An XML element
  */
+#[deriving(Clone)]
 pub struct BorrowedElement<'r> {
     priv node: &'r ffi::xmlNode
 }
@@ -90,6 +260,69 @@ pub struct BorrowedElement<'r> {
  * Iterator over children of XML element
  */
 pub struct ElementChildrenIterator<'r> {
+    priv cur: Option<&'r ffi::xmlNode>,
+    priv back: Option<&'r ffi::xmlNode>
+}
+
+/**
+ * Iterator over children of XML element which are elements
+ */
+pub struct ChildElementIterator<'r> {
+    priv children: ElementChildrenIterator<'r>
+}
+
+/**
+ * Iterator over children of XML element with given name
+ */
+pub struct NamedChildElementIterator<'r> {
+    priv children: ChildElementIterator<'r>,
+    priv name: ElementName
+}
+
+/**
+ * Iterator over descendants of XML element with given name
+ */
+pub struct NamedDescendantIterator<'r> {
+    priv descendants: DescendantIterator<'r>,
+    priv name: ElementName
+}
+
+// Local name and namespace URI matched by named iterators.
+#[deriving(Clone)]
+struct ElementName {
+    local: ~str,
+    ns: Option<~str>
+}
+
+/**
+ * Walker over all nodes under XML element, producing an event on entering
+ * and on leaving each of them
+ */
+pub struct TreeWalker<'r> {
+    priv root: &'r ffi::xmlNode,
+    priv cur: Option<&'r ffi::xmlNode>,
+    priv entering: bool,
+    priv entered: Option<&'r ffi::xmlNode>
+}
+
+/**
+ * Iterator over descendants of XML element in document order (pre-order)
+ */
+pub struct DescendantIterator<'r> {
+    priv walker: TreeWalker<'r>
+}
+
+/**
+ * Iterator over descendants of XML element in post-order
+ */
+pub struct PostOrderIterator<'r> {
+    priv walker: TreeWalker<'r>
+}
+
+/**
+ * Iterator over ancestor elements, starting from the parent
+ */
+pub struct AncestorIterator<'r> {
     priv cur: Option<&'r ffi::xmlNode>
 }
 
@@ -107,6 +340,13 @@ pub struct BorrowedNamespace<'r> {
     priv ns: &'r ffi::xmlNs
 }
 
+/**
+ * Iterator over namespaces declared on XML element
+ */
+pub struct NamespaceIterator<'r> {
+    priv cur: Option<&'r ffi::xmlNs>
+}
+
 /**
  * Text inside an XML.
  */
@@ -114,16 +354,71 @@ pub struct BorrowedText<'r> {
     priv node: &'r ffi::xmlNode
 }
 
+/**
+ * Description of an error in a document, such as not being well-formed or
+ * valid, or in a schema.
+ */
+#[deriving(Clone, Eq)]
+pub struct ParseError {
+    /// Message reported by libxml2.
+    message: ~str,
+    /// Line of the error, 0 if unknown.
+    line: uint,
+    /// Column of the error, 0 if unknown.
+    column: uint
+}
+
+/**
+ * Failure of applying a patch to a document.
+ */
+#[deriving(Clone, Eq)]
+pub struct PatchError {
+    /// Description of the failure.
+    message: ~str,
+    /// Selector of the failed operation, empty if not applicable.
+    selector: ~str
+}
+
+/**
+ * Reason of failure to read a document.
+ */
+pub enum ReadError {
+    /// The reader failed.
+    IoReadError(std::io::IoError),
+    /// The document is not well-formed.
+    ParseReadError(ParseError)
+}
+
+/**
+ * Reason of failure to write a document.
+ */
+pub enum WriteError {
+    /// The writer failed.
+    IoWriteError(std::io::IoError),
+    /// libxml2 failed to serialize the document.
+    SaveWriteError
+}
+
 /**
  * Possible children of an attribute.
  */
+#[deriving(Eq, TotalEq, IterBytes)]
 pub enum AttributeChild<'r> {
     TextAttributeChild(BorrowedText<'r>)
 }
 
+/**
+ * Event produced by tree walker.
+ */
+pub enum WalkEvent<'r> {
+    EnterWalkEvent(ElementChild<'r>),
+    LeaveWalkEvent(ElementChild<'r>)
+}
+
 /**
  * Possible children of an element.
  */
+#[deriving(Eq, TotalEq, IterBytes)]
 pub enum ElementChild<'r> {
     ElementElementChild(BorrowedElement<'r>),
     TextElementChild(BorrowedText<'r>),
@@ -131,24 +426,343 @@ pub enum ElementChild<'r> {
     CommentElementChild(BorrowedComment<'r>)
 }
 
+/**
+ * Type of content allowed by element declaration.
+ */
+#[deriving(Clone, Eq, ToStr)]
+pub enum ElementContentType {
+    UndefinedContentType,
+    EmptyContentType,
+    AnyContentType,
+    MixedContentType,
+    ElementsContentType
+}
+
+/**
+ * Number of occurrences allowed for a content particle.
+ */
+#[deriving(Clone, Eq, ToStr)]
+pub enum Occurrence {
+    /// No suffix - exactly once.
+    OnceOccurrence,
+    /// `?` - zero or one time.
+    OptOccurrence,
+    /// `*` - zero or more times.
+    MultOccurrence,
+    /// `+` - one or more times.
+    PlusOccurrence
+}
+
+/**
+ * Content model of an element declaration.
+ */
+#[deriving(Clone, Eq, ToStr)]
+pub enum ContentParticle {
+    /// `#PCDATA`
+    PCDataParticle,
+    /// Element with given (possibly prefixed) name.
+    ElementParticle(~str, Occurrence),
+    /// Sequence `(a, b, c)`.
+    SeqParticle(~[ContentParticle], Occurrence),
+    /// Choice `(a | b | c)`.
+    ChoiceParticle(~[ContentParticle], Occurrence)
+}
+
+/**
+ * Type of an attribute.
+ */
+#[deriving(Clone, Eq, ToStr)]
+pub enum AttributeType {
+    CDataAttribute,
+    IdAttribute,
+    IdRefAttribute,
+    IdRefsAttribute,
+    EntityAttribute,
+    EntitiesAttribute,
+    NmTokenAttribute,
+    NmTokensAttribute,
+    EnumerationAttribute,
+    NotationAttribute
+}
+
+/**
+ * Kind of default declared for an attribute.
+ */
+#[deriving(Clone, Eq, ToStr)]
+pub enum AttributeDefault {
+    /// Plain default value.
+    ValueDefault,
+    /// `#REQUIRED`
+    RequiredDefault,
+    /// `#IMPLIED`
+    ImpliedDefault,
+    /// `#FIXED`
+    FixedDefault
+}
+
+/**
+ * Type of an entity.
+ */
+#[deriving(Clone, Eq, ToStr)]
+pub enum EntityType {
+    InternalGeneralEntity,
+    ExternalGeneralParsedEntity,
+    ExternalGeneralUnparsedEntity,
+    InternalParameterEntity,
+    ExternalParameterEntity,
+    InternalPredefinedEntity
+}
+
+/**
+ * What to do with strings which are not valid UTF-8 when reading a document.
+ *
+ * The check is done once, when the document is read, so that the borrowed
+ * `*_str` accessors can hand out `&str` without copying. Every other way of
+ * adding strings to a document takes them as `&str` or checks them, so a
+ * document never holds invalid UTF-8. The `*_bytes` accessors give the raw
+ * bytes as stored by libxml2.
+ */
+#[deriving(Clone, Eq, ToStr)]
+pub enum Utf8Policy {
+    /// Fail to read the document.
+    RejectInvalidUtf8,
+    /// Replace invalid sequences with U+FFFD REPLACEMENT CHARACTER. The
+    /// document must be otherwise well-formed. Input which turns out not to
+    /// be valid UTF-8 is read again with the sequences replaced, so it is
+    /// kept in memory.
+    ReplaceInvalidUtf8
+}
+
 impl Document {
+    /**
+     * Borrow the document for navigation from nodes.
+     */
+    pub fn borrow<'r>(&'r self) -> BorrowedDocument<'r> {
+        unsafe {
+            BorrowedDocument {doc: &*self.doc}
+        }
+    }
+
     /**
      * Find the root element, if it exists.
      */
     pub fn get_root_element<'r>(&'r self) -> Option<BorrowedElement<'r>> {
+        self.borrow().get_root_element()
+    }
+
+    /**
+     * Copy the whole document, including the DTD.
+     */
+    pub fn deep_copy(&self) -> Document {
+        let doc = unsafe {ffi::xmlCopyDoc(self.doc, 1)};
+        if std::ptr::is_null(doc) {
+            fail!("Failed to copy document");
+        }
+        Document {doc: doc}
+    }
+
+    /**
+     * Copy element, possibly from other document, into this document. If
+     * `deep` is not set only the element with its attributes is copied,
+     * without children. Namespaces used by the copy are declared on it.
+     */
+    pub fn import_node<'r>(&'r self, node: &BorrowedElement, deep: bool) -> DetachedNode<'r> {
+        let copy = unsafe {ffi::xmlDocCopyNode(node.node, self.doc, if deep {1} else {2})};
+        if std::ptr::is_null(copy) {
+            fail!("Failed to copy node");
+        }
         unsafe {
-            ptr_to_option(ffi::xmlDocGetRootElement(self.doc)).map(|elem| BorrowedElement{node: &*elem})
+            DetachedNode {node: copy, doc: &*self.doc}
         }
     }
 
     /**
-     * Parse the document from reader.
+     * Find the internal subset (the DTD in `<!DOCTYPE ... [...]>`), if it exists.
      */
-    pub fn read(reader: &mut Reader) -> Option<Document> {
-        use std::io::{IoError,EndOfFile,io_error};
-        use std::libc::{c_char, c_int, c_void};
+    pub fn internal_subset<'r>(&'r self) -> Option<BorrowedDtd<'r>> {
+        self.borrow().internal_subset()
+    }
+
+    /**
+     * Find the external subset, if it was loaded.
+     */
+    pub fn external_subset<'r>(&'r self) -> Option<BorrowedDtd<'r>> {
+        self.borrow().external_subset()
+    }
+
+    /**
+     * Find the element with given ID. Only attributes declared as ID in DTD
+     * and `xml:id` attributes are taken into account.
+     */
+    pub fn get_element_by_id<'r>(&'r self, id: &str) -> Option<BorrowedElement<'r>> {
+        self.borrow().get_element_by_id(id)
+    }
+
+    /**
+     * Gets the URL the document was loaded from, if known.
+     */
+    pub fn url(&self) -> Option<~str> {
+        self.borrow().url()
+    }
+
+    /**
+     * Remove namespace declarations which repeat a declaration already in
+     * scope. If `hoist` is set, prefixes bound to the same URI everywhere in
+     * the document are first declared on the root element, so that the
+     * nested declarations become redundant. Returns the number of removed
+     * declarations.
+     */
+    pub fn remove_redundant_namespaces(&mut self, hoist: bool) -> uint {
+        unsafe {
+            let root = ffi::xmlDocGetRootElement(self.doc);
+            if std::ptr::is_null(root) {
+                return 0;
+            }
+            if hoist {
+                hoist_namespaces(self.doc, root);
+            }
+            let mut removed = 0;
+            for &elem in collect_elements(root).iter() {
+                let parent = (*elem).parent;
+                if std::ptr::is_null(parent) || !is_element(parent) {
+                    continue;
+                }
+                let mut ns = (*elem).nsDef;
+                while std::ptr::is_not_null(ns) {
+                    let next = (*ns).next;
+                    let inherited = ffi::xmlSearchNs(self.doc, parent, (*ns).prefix);
+                    if std::ptr::is_not_null(inherited) && ffi::xmlStrEqual((*inherited).href, (*ns).href) != 0 {
+                        unlink_ns(elem, ns);
+                        remap_ns(elem, ns, inherited);
+                        ffi::xmlFreeNs(ns);
+                        removed += 1;
+                    }
+                    ns = next;
+                }
+            }
+            removed
+        }
+    }
+
+    /**
+     * Use the prefix for all declarations of namespace URI in the document.
+     * Nothing is changed and `false` is returned if the prefix is not a
+     * valid one or is already bound to other URI somewhere in the document.
+     */
+    pub fn rename_namespace_prefix(&mut self, href: &str, prefix: &str) -> bool {
+        if prefix.contains_char('\0') || prefix == "xml" || prefix == "xmlns" {
+            return false;
+        }
+        if prefix.with_c_str(|prefix| unsafe {ffi::xmlValidateNCName(prefix as *ffi::xmlChar, 0)}) != 0 {
+            return false;
+        }
+        unsafe {
+            let root = ffi::xmlDocGetRootElement(self.doc);
+            if std::ptr::is_null(root) {
+                return true;
+            }
+            let elements = collect_elements(root);
+            for &elem in elements.iter() {
+                let mut ns = (*elem).nsDef;
+                while std::ptr::is_not_null(ns) {
+                    if xml_str_eq((*ns).prefix, prefix) && !xml_str_eq((*ns).href, href) {
+                        return false;
+                    }
+                    ns = (*ns).next;
+                }
+            }
+            prefix.with_c_str(|prefix_ptr| {
+                let prefix_ptr = prefix_ptr as *ffi::xmlChar;
+                for &elem in elements.iter() {
+                    let mut ns = (*elem).nsDef;
+                    while std::ptr::is_not_null(ns) {
+                        let next = (*ns).next;
+                        if xml_str_eq((*ns).href, href) && !xml_str_eq((*ns).prefix, prefix) {
+                            let mut existing = (*elem).nsDef;
+                            while std::ptr::is_not_null(existing) && !xml_str_eq((*existing).prefix, prefix) {
+                                existing = (*existing).next;
+                            }
+                            if std::ptr::is_not_null(existing) {
+                                // Element already declares the prefix - merge both declarations
+                                unlink_ns(elem, ns);
+                                remap_ns(elem, ns, existing);
+                                ffi::xmlFreeNs(ns);
+                            } else {
+                                let ns = ns as *mut ffi::xmlNs;
+                                if std::ptr::is_not_null((*ns).prefix) {
+                                    xml_free((*ns).prefix);
+                                }
+                                (*ns).prefix = ffi::xmlStrdup(prefix_ptr);
+                            }
+                        }
+                        ns = next;
+                    }
+                }
+            });
+            true
+        }
+    }
+
+    /**
+     * Apply XML patch (RFC 5261) with add, replace and remove operations.
+     * If any operation fails the document is left unchanged.
+     */
+    pub fn apply_patch(&mut self, patch: &Document) -> Result<(), PatchError> {
+        let diff = match patch.get_root_element() {
+            Some(diff) => diff,
+            None => return Err(PatchError {message: ~"Patch has no root element", selector: ~""})
+        };
+        let mut patched = self.deep_copy();
+        match patch::apply(patched.doc, &diff) {
+            Ok(()) => {
+                std::util::swap(self, &mut patched);
+                Ok(())
+            },
+            Err(err) => Err(err)
+        }
+    }
+
+    /**
+     * Parse the document from reader, rejecting documents which are not
+     * valid UTF-8. Gzip compressed input is decompressed transparently.
+     */
+    pub fn read(reader: &mut Reader) -> Result<Document, ReadError> {
+        Document::read_with_policy(reader, RejectInvalidUtf8)
+    }
+
+    /**
+     * Parse the document from reader, treating invalid UTF-8 according to
+     * the policy.
+     */
+    pub fn read_with_policy(reader: &mut Reader, policy: Utf8Policy) -> Result<Document, ReadError> {
+        Document::read_with_options(reader, policy, None, PARSE_OPTIONS)
+    }
+
+    /**
+     * Parse the document from reader, loading external DTDs and entities
+     * through resolver and substituting entities. Relative references are
+     * resolved against base URL. Parsing fails if any resource could not be
+     * loaded.
+     */
+    pub fn read_with_resolver(reader: &mut Reader, base: Option<&str>,
+                              resolver: &resolver::ResourceResolver) -> Result<Document, ReadError> {
+        let options = PARSE_OPTIONS | 2 /* Substitute entities */ |
+                      4 /* Load external DTD */ | 8 /* Default attributes */;
+        match resolver::with_resolver(resolver, base, || {
+            Document::read_with_options(reader, RejectInvalidUtf8, base, options)
+        }) {
+            (Ok(_), Some(err)) | (Err(_), Some(err)) => Err(IoReadError(err)),
+            (result, None) => result
+        }
+    }
+
+    fn read_with_options(reader: &mut Reader, policy: Utf8Policy, url: Option<&str>,
+                         options: std::libc::c_int) -> Result<Document, ReadError> {
         use std::ptr::{null,to_mut_unsafe_ptr};
-        unsafe {ffi::xmlCheckVersion(ffi::xmlVersion)};
+        if policy == ReplaceInvalidUtf8 {
+            return Document::read_replacing(reader, url, options);
+        }
+        init();
         struct Context<'t> {
             reader: &'t mut Reader,
             ioerror: Option<IoError>
@@ -174,51 +788,236 @@ impl Document {
             }
         }
         extern "C" fn ioclose(_: *mut c_void) -> c_int {0};
+        let mut reader = gzip::DetectingReader::new(reader);
         let mut context = Context {
-            reader: reader,
+            reader: &mut reader as &mut Reader,
             ioerror: None
         };
         let doc = unsafe {
             let ctx = &mut context;
-            ffi::xmlReadIO(ioread, ioclose,
-                           to_mut_unsafe_ptr(ctx) as *mut c_void,
-                           null(), null(),
-                           64 | 32 /* No errors or warnings for now */)
+            ffi::xmlResetLastError();
+            let read = |url| ffi::xmlReadIO(ioread, ioclose,
+                                            to_mut_unsafe_ptr(ctx) as *mut c_void,
+                                            url, null(),
+                                            options);
+            match url {
+                Some(url) => url.with_c_str(|url| read(url)),
+                None => read(null())
+            }
         };
-        match (context.ioerror, ptr_to_option(doc)) {
-            (Some(err), _) => {io_error::cond.raise(err); None},
-            (None, None) => None,
-            (None, Some(doc)) => Some(Document {doc: doc}),
+        match context.ioerror {
+            Some(err) => {
+                ptr_to_option(doc).map(|doc| unsafe {ffi::xmlFreeDoc(doc)});
+                Err(IoReadError(err))
+            },
+            None => Document::from_parsed(doc, policy, last_parse_error()).map_err(|err| ParseReadError(err))
         }
     }
 
-    /**
-     * Write document to writer
-     */
-    pub fn write(&self, writer: &mut Writer) {
-        use std::io::{IoError,io_error};
-        use std::libc::{c_char, c_int, c_void};
-        use std::ptr::{null,to_mut_unsafe_ptr};
-        struct Context<'t> {
-             writer: &'t mut Writer,
-             ioerror: Option<IoError>
+    // Reads the input as is and, if libxml2 rejects it as invalid UTF-8,
+    // again with invalid sequences replaced. Recovering from the error
+    // instead would let libxml2 decode the rest of the input as Latin-1.
+    fn read_replacing(reader: &mut Reader, url: Option<&str>,
+                      options: std::libc::c_int) -> Result<Document, ReadError> {
+        use std::io::io_error;
+        use std::io::mem::BufReader;
+        let mut error = None;
+        let bytes = io_error::cond.trap(|err| error = Some(err)).inside(|| {
+            gzip::DetectingReader::new(reader).read_to_end()
+        });
+        match error {
+            Some(err) => return Err(IoReadError(err)),
+            None => {}
         }
-        unsafe fn get_context<'t>(context_ptr: &'t *mut c_void) -> &'t mut Context<'t> {
-            &mut *(context_ptr.clone() as *mut Context<'t>)
+        let read = |bytes: &[u8]| {
+            Document::read_with_options(&mut BufReader::new(bytes), RejectInvalidUtf8, url, options)
+        };
+        match read(bytes.as_slice()) {
+            Err(ParseReadError(ref err)) if err.message.starts_with("Input is not proper UTF-8") => {
+                read(lossy_utf8(bytes).as_bytes())
+            },
+            result => result
         }
-        extern "C" fn iowrite(context_ptr: *mut c_void, buf: *c_char, len: c_int) -> c_int {
-            unsafe {
-                let context = get_context(&context_ptr);
-                let vec = std::vec::raw::from_buf_raw(buf as *u8, len as uint);
-                io_error::cond.trap(|err| (*context).ioerror = Some(err)).inside(|| {
-                    context.writer.write(vec);
-                });
-                context.ioerror.as_ref().map_default(len, |_| -1)
-            }
+    }
+
+    /**
+     * Parse the document from bytes, rejecting documents which are not
+     * valid UTF-8. Gzip compressed input is decompressed transparently.
+     */
+    pub fn from_bytes(bytes: &[u8]) -> Result<Document, ParseError> {
+        use std::io::mem::BufReader;
+        use std::ptr::null;
+        if bytes.starts_with([0x1f, 0x8b]) {
+            return match Document::read(&mut BufReader::new(bytes)) {
+                Ok(doc) => Ok(doc),
+                Err(ParseReadError(err)) => Err(err),
+                Err(IoReadError(err)) => Err(ParseError {
+                    message: err.desc.to_owned(),
+                    line: 0,
+                    column: 0
+                })
+            };
         }
-        extern "C" fn ioclose(context_ptr: *mut c_void) -> c_int {
+        init();
+        let doc = unsafe {
+            ffi::xmlResetLastError();
+            ffi::xmlReadMemory(bytes.as_ptr() as *std::libc::c_char, bytes.len() as std::libc::c_int,
+                               null(), null(), PARSE_OPTIONS)
+        };
+        Document::from_parsed(doc, RejectInvalidUtf8, last_parse_error())
+    }
+
+    /**
+     * Parse the document from string. Encoding declared in the document is
+     * ignored.
+     */
+    pub fn from_str(s: &str) -> Result<Document, ParseError> {
+        use std::ptr::null;
+        init();
+        let doc = unsafe {
+            ffi::xmlResetLastError();
+            "UTF-8".with_c_str(|encoding| {
+                ffi::xmlReadMemory(s.as_bytes().as_ptr() as *std::libc::c_char, s.len() as std::libc::c_int,
+                                   null(), encoding, PARSE_OPTIONS)
+            })
+        };
+        Document::from_parsed(doc, RejectInvalidUtf8, last_parse_error())
+    }
+
+    /**
+     * Parse the document from file. The path is recorded as URL of the
+     * document, so relative references are resolved against it.
+     */
+    pub fn from_path(path: &Path) -> Result<Document, ReadError> {
+        use std::io::{IoError,OtherIoError};
+        use std::ptr::null;
+        init();
+        unsafe {
+            let ctxt = ffi::xmlNewParserCtxt();
+            if std::ptr::is_null(ctxt) {
+                fail!("Failed to allocate parser context");
+            }
+            let doc = path.with_c_str(|filename| ffi::xmlCtxtReadFile(ctxt, filename, null(), PARSE_OPTIONS));
+            let error = ptr_to_option(ffi::xmlCtxtGetLastError(ctxt)).map(|error| ((*error).domain, parse_error(error)));
+            ffi::xmlFreeParserCtxt(ctxt);
+            match (ptr_to_option(doc), error) {
+                (None, Some((8 /* XML_FROM_IO */, error))) => Err(IoReadError(IoError {
+                    kind: OtherIoError,
+                    desc: "Failed to read file",
+                    detail: Some(error.message)
+                })),
+                (_, error) => {
+                    let error = error.map_default(unknown_parse_error(), |(_, error)| error);
+                    Document::from_parsed(doc, RejectInvalidUtf8, error).map_err(|err| ParseReadError(err))
+                }
+            }
+        }
+    }
+
+    // Wraps result of parsing, checking strings according to policy.
+    fn from_parsed(doc: *ffi::xmlDoc, policy: Utf8Policy, error: ParseError) -> Result<Document, ParseError> {
+        match ptr_to_option(doc) {
+            None => Err(error),
+            Some(doc) => {
+                if unsafe {check_utf8(doc as *ffi::xmlNode, policy)} {
+                    Ok(Document {doc: doc})
+                } else {
+                    unsafe {ffi::xmlFreeDoc(doc)};
+                    Err(ParseError {
+                        message: ~"Document is not valid UTF-8",
+                        line: 0,
+                        column: 0
+                    })
+                }
+            }
+        }
+    }
+
+    /**
+     * Write document to writer, returning the number of written bytes. The
+     * output is gzip compressed if compression level is set.
+     */
+    pub fn write(&self, writer: &mut Writer) -> Result<uint, WriteError> {
+        use std::io::io_error;
+        match self.compression() {
+            0 => self.write_plain(writer),
+            level => {
+                let mut writer = gzip::CompressingWriter::new(writer, level);
+                match self.write_plain(&mut writer as &mut Writer) {
+                    Ok(_) => {
+                        let mut ioerror = None;
+                        let written = io_error::cond.trap(|err| ioerror = Some(err)).inside(|| {
+                            writer.finish()
+                        });
+                        match ioerror {
+                            Some(err) => Err(IoWriteError(err)),
+                            None => Ok(written)
+                        }
+                    },
+                    Err(err) => Err(err)
+                }
+            }
+        }
+    }
+
+    /**
+     * Compression level used by write and save_to_path, from 0 (none) to
+     * 9.
+     */
+    pub fn compression(&self) -> uint {
+        match unsafe {(*self.doc).compression} {
+            level if level < 0 => 0,
+            level if level > 9 => 9,
+            level => level as uint
+        }
+    }
+
+    /**
+     * Set compression level, from 0 (none) to 9. Larger values are treated
+     * as 9.
+     */
+    pub fn set_compression(&mut self, level: uint) {
+        unsafe {
+            let doc = self.doc as *mut ffi::xmlDoc;
+            (*doc).compression = std::num::min(level, 9) as std::libc::c_int;
+        }
+    }
+
+    fn write_plain(&self, writer: &mut Writer) -> Result<uint, WriteError> {
+        use std::io::{IoError,io_error};
+        use std::libc::{c_char, c_int, c_void};
+        use std::ptr::{null,to_mut_unsafe_ptr};
+        init();
+        struct Context<'t> {
+             writer: &'t mut Writer,
+             ioerror: Option<IoError>,
+             written: uint
+        }
+        unsafe fn get_context<'t>(context_ptr: &'t *mut c_void) -> &'t mut Context<'t> {
+            &mut *(context_ptr.clone() as *mut Context<'t>)
+        }
+        extern "C" fn iowrite(context_ptr: *mut c_void, buf: *c_char, len: c_int) -> c_int {
+            unsafe {
+                let context = get_context(&context_ptr);
+                if context.ioerror.is_some() {
+                    return -1;
+                }
+                let vec = std::vec::raw::from_buf_raw(buf as *u8, len as uint);
+                io_error::cond.trap(|err| (*context).ioerror = Some(err)).inside(|| {
+                    context.writer.write(vec);
+                });
+                if context.ioerror.is_none() {
+                    context.written += len as uint;
+                }
+                context.ioerror.as_ref().map_default(len, |_| -1)
+            }
+        }
+        extern "C" fn ioclose(context_ptr: *mut c_void) -> c_int {
             unsafe {
                 let context = get_context(&context_ptr);
+                if context.ioerror.is_some() {
+                    return -1;
+                }
                 io_error::cond.trap(|err| (*context).ioerror = Some(err)).inside(|| {
                     context.writer.flush();
                 });
@@ -227,134 +1026,1281 @@ impl Document {
         }
         let mut context = Context {
             writer: writer,
-            ioerror: None
+            ioerror: None,
+            written: 0
         };
-        unsafe {
+        let saved = unsafe {
             let ctx = &mut context;
             let save_ctx = ffi::xmlSaveToIO(iowrite, ioclose, to_mut_unsafe_ptr(ctx) as *mut c_void, null(), 0);
-            ffi::xmlSaveDoc(save_ctx, self.doc);
-            ffi::xmlSaveClose(save_ctx);
+            if std::ptr::is_null(save_ctx) {
+                false
+            } else {
+                let saved = ffi::xmlSaveDoc(save_ctx, self.doc) >= 0;
+                ffi::xmlSaveClose(save_ctx) >= 0 && saved
+            }
+        };
+        match (context.ioerror, saved) {
+            (Some(err), _) => Err(IoWriteError(err)),
+            (None, false) => Err(SaveWriteError),
+            (None, true) => Ok(context.written)
+        }
+    }
+
+    /**
+     * Serialize the document in its encoding.
+     */
+    pub fn to_bytes(&self) -> ~[u8] {
+        self.dump(std::ptr::null(), false)
+    }
+
+    /**
+     * Serialize the document to string.
+     */
+    pub fn to_string(&self) -> ~str {
+        let bytes = "UTF-8".with_c_str(|encoding| self.dump(encoding, false));
+        std::str::from_utf8_owned(bytes)
+    }
+
+    /**
+     * Serialize the document to string, indenting elements. Whitespace
+     * between elements is discarded, while text in mixed content is kept
+     * as is.
+     */
+    pub fn to_formatted_string(&self) -> ~str {
+        let copy = self.deep_copy();
+        unsafe {
+            ptr_to_option(ffi::xmlDocGetRootElement(copy.doc)).map(|root| strip_blank_text(root));
+        }
+        let bytes = "UTF-8".with_c_str(|encoding| copy.dump(encoding, true));
+        std::str::from_utf8_owned(bytes)
+    }
+
+    /**
+     * Serialize the document in Canonical XML 1.0 form, optionally
+     * keeping comments.
+     */
+    pub fn canonicalize(&self, with_comments: bool) -> ~[u8] {
+        use std::ptr::{is_null, null};
+        init();
+        unsafe {
+            let mut mem = null();
+            let size = ffi::xmlC14NDocDumpMemory(self.doc, null(), 0, null(), with_comments as std::libc::c_int, &mut mem);
+            if size < 0 || is_null(mem) {
+                fail!("Failed to canonicalize document");
+            }
+            let bytes = std::vec::raw::from_buf_raw(mem, size as uint);
+            xml_free(mem);
+            bytes
+        }
+    }
+
+    /**
+     * Save the document to file in its encoding, returning the number of
+     * written bytes. The file is gzip compressed if compression level is
+     * set.
+     */
+    pub fn save_to_path(&self, path: &Path) -> Result<uint, WriteError> {
+        use std::io::{IoError,OtherIoError};
+        use std::ptr::null;
+        init();
+        match path.with_c_str(|filename| unsafe {ffi::xmlSaveFileEnc(filename, self.doc, null())}) {
+            written if written >= 0 => Ok(written as uint),
+            _ => Err(IoWriteError(IoError {
+                kind: OtherIoError,
+                desc: "Failed to save document",
+                detail: Some(format!("{}", path.display()))
+            }))
+        }
+    }
+
+    fn dump(&self, encoding: *std::libc::c_char, format: bool) -> ~[u8] {
+        use std::ptr::null;
+        init();
+        unsafe {
+            let mut mem = null();
+            let mut size = 0;
+            ffi::xmlDocDumpFormatMemoryEnc(self.doc, &mut mem, &mut size, encoding, format as std::libc::c_int);
+            if std::ptr::is_null(mem) {
+                fail!("Failed to serialize document");
+            }
+            let bytes = std::vec::raw::from_buf_raw(mem, size as uint);
+            xml_free(mem);
+            bytes
+        }
+    }
+}
+
+#[unsafe_destructor]
+impl Drop for Document {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::xmlFreeDoc(self.doc);
+        }
+    }
+}
+
+impl Clone for Document {
+    fn clone(&self) -> Document {
+        self.deep_copy()
+    }
+}
+
+impl ToStr for ParseError {
+    fn to_str(&self) -> ~str {
+        format!("{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl ToStr for PatchError {
+    fn to_str(&self) -> ~str {
+        format!("{}: {}", self.selector, self.message)
+    }
+}
+
+impl ToStr for ReadError {
+    fn to_str(&self) -> ~str {
+        match *self {
+            IoReadError(ref err) => err.to_str(),
+            ParseReadError(ref err) => err.to_str()
+        }
+    }
+}
+
+impl ToStr for WriteError {
+    fn to_str(&self) -> ~str {
+        match *self {
+            IoWriteError(ref err) => err.to_str(),
+            SaveWriteError => ~"Failed to serialize document"
+        }
+    }
+}
+
+impl<'r> DetachedNode<'r> {
+    /**
+     * Borrow the node.
+     */
+    pub fn get<'t>(&'t self) -> ElementChild<'t> {
+        unsafe {
+            element_child(&*self.node).unwrap()
+        }
+    }
+    // Gives up the ownership of node.
+    fn take(mut self) -> *ffi::xmlNode {
+        let node = self.node;
+        self.node = std::ptr::null();
+        node
+    }
+}
+
+#[unsafe_destructor]
+impl<'r> Drop for DetachedNode<'r> {
+    fn drop(&mut self) {
+        if std::ptr::is_not_null(self.node) {
+            unsafe {
+                ffi::xmlFreeNode(self.node);
+            }
+        }
+    }
+}
+
+impl<'r> BorrowedDocument<'r> {
+    /**
+     * Find the root element, if it exists.
+     */
+    pub fn get_root_element(&self) -> Option<BorrowedElement<'r>> {
+        unsafe {
+            ptr_to_option(ffi::xmlDocGetRootElement(self.doc)).map(|elem| BorrowedElement{node: &*elem})
+        }
+    }
+    /**
+     * Find the internal subset (the DTD in `<!DOCTYPE ... [...]>`), if it exists.
+     */
+    pub fn internal_subset(&self) -> Option<BorrowedDtd<'r>> {
+        unsafe {
+            ptr_to_option(self.doc.intSubset).map(|dtd| BorrowedDtd{dtd: &*dtd})
+        }
+    }
+    /**
+     * Find the external subset, if it was loaded.
+     */
+    pub fn external_subset(&self) -> Option<BorrowedDtd<'r>> {
+        unsafe {
+            ptr_to_option(self.doc.extSubset).map(|dtd| BorrowedDtd{dtd: &*dtd})
+        }
+    }
+    /**
+     * Find the element with given ID. Only attributes declared as ID in DTD
+     * and `xml:id` attributes are taken into account.
+     */
+    pub fn get_element_by_id(&self, id: &str) -> Option<BorrowedElement<'r>> {
+        element_by_id(self.doc, id)
+    }
+    /**
+     * Gets the URL the document was loaded from, if known.
+     */
+    pub fn url(&self) -> Option<~str> {
+        unsafe {
+            ptr_to_option(self.doc.url).map(|url| owned_str(url as *ffi::xmlChar))
+        }
+    }
+}
+
+impl<'r> BorrowedCData<'r> {
+    /// Get contents without copying.
+    pub fn content_str(&self) -> &'r str {
+        unsafe {
+            borrow_str(self.node.content)
+        }
+    }
+    /// Get contents as bytes without copying.
+    pub fn content_bytes(&self) -> &'r [u8] {
+        unsafe {
+            borrow_bytes(self.node.content)
+        }
+    }
+}
+
+impl<'r> BorrowedComment<'r> {
+    /// Get comment contents.
+    pub fn comment(&self) -> ~str {
+        self.comment_str().to_owned()
+    }
+    /// Get comment contents without copying.
+    pub fn comment_str(&self) -> &'r str {
+        unsafe {
+            borrow_str(self.node.content)
+        }
+    }
+    /// Get comment contents as bytes without copying.
+    pub fn comment_bytes(&self) -> &'r [u8] {
+        unsafe {
+            borrow_bytes(self.node.content)
+        }
+    }
+}
+
+impl<'r> BorrowedAttribute<'r> {
+    /**
+     * Gets the name without copying
+     */
+    pub fn name_str(&self) -> &'r str {
+        unsafe {
+            borrow_str(self.attr.name as *ffi::xmlChar)
+        }
+    }
+    /**
+     * Gets the name as bytes without copying
+     */
+    pub fn name_bytes(&self) -> &'r [u8] {
+        unsafe {
+            borrow_bytes(self.attr.name as *ffi::xmlChar)
+        }
+    }
+    /**
+     * Iterate over children
+     */
+    pub fn children_iter(&self) -> AttributeChildrenIterator<'r> {
+        AttributeChildrenIterator {
+            cur: ptr_to_option(self.attr.children).map(|cur| unsafe {&*cur})
+        }
+    }
+    /**
+     * Gets the value of the attribute
+     */
+    pub fn value(&self) -> ~str {
+        self.children_iter().map(|c| {
+            match c {
+                TextAttributeChild(t) => t.content()
+            }
+        }).to_owned_vec().concat()
+    }
+    /**
+     * Gets the element the attribute belongs to
+     */
+    pub fn owner_element(&self) -> BorrowedElement<'r> {
+        unsafe {
+            BorrowedElement {node: &*self.attr.parent}
+        }
+    }
+    /**
+     * Gets the document the attribute belongs to
+     */
+    pub fn owner_document(&self) -> BorrowedDocument<'r> {
+        unsafe {
+            BorrowedDocument {doc: &*self.attr.doc}
+        }
+    }
+    /**
+     * Gets the line on which the attribute was parsed. It is the line of
+     * the start tag of its element.
+     */
+    pub fn line(&self) -> Option<uint> {
+        unsafe {
+            node_line(&*self.attr.parent)
+        }
+    }
+    /**
+     * Find the declaration of the attribute in internal or external subset
+     */
+    pub fn declaration(&self) -> Option<BorrowedAttributeDecl<'r>> {
+        use std::ptr::null;
+        unsafe {
+            let doc = &*self.attr.doc;
+            let elem = &*self.attr.parent;
+            let prefix = ptr_to_option(self.attr.ns).and_then(|ns| ptr_to_option((*ns).prefix));
+            let elem_name = match ptr_to_option(elem.ns).and_then(|ns| ptr_to_option((*ns).prefix)) {
+                Some(p) => owned_str(p) + ":" + owned_str(elem.name),
+                None => owned_str(elem.name)
+            };
+            elem_name.with_c_str(|elem_name| {
+                let mut decl = None;
+                for &dtd in [doc.intSubset, doc.extSubset].iter() {
+                    if decl.is_none() && std::ptr::is_not_null(dtd) {
+                        decl = ptr_to_option(ffi::xmlGetDtdQAttrDesc(dtd, elem_name as *ffi::xmlChar,
+                                                                      self.attr.name as *ffi::xmlChar,
+                                                                      prefix.unwrap_or(null())));
+                    }
+                }
+                decl.map(|decl| BorrowedAttributeDecl {decl: &*decl})
+            })
+        }
+    }
+    /**
+     * Gets the type of the attribute. Attributes which are not declared
+     * are treated as CDATA.
+     */
+    pub fn attr_type(&self) -> AttributeType {
+        if self.attr.atype == ffi::ID as std::libc::c_int {
+            IdAttribute
+        } else {
+            self.declaration().map_default(CDataAttribute, |decl| decl.attr_type())
+        }
+    }
+    /**
+     * Find elements referenced by IDREF or IDREFS attribute. References
+     * which cannot be resolved are skipped and for other types of
+     * attributes nothing is returned.
+     */
+    pub fn referenced_elements(&self) -> ~[BorrowedElement<'r>] {
+        match self.attr_type() {
+            IdRefAttribute | IdRefsAttribute => {
+                let value = self.value();
+                value.words().filter_map(|id| element_by_id(self.attr.doc, id)).collect()
+            },
+            _ => ~[]
+        }
+    }
+}
+
+impl<'r> BorrowedDtd<'r> {
+    /**
+     * Gets the name of the root element given in DOCTYPE
+     */
+    pub fn name(&self) -> ~str {
+        unsafe {
+            owned_str(self.dtd.name as *ffi::xmlChar)
         }
-        context.ioerror.map(|e| {io_error::cond.raise(e)});
+    }
+    /**
+     * Gets the public identifier
+     */
+    pub fn public_id(&self) -> Option<~str> {
+        unsafe {
+            ptr_to_option(self.dtd.externalId).map(|id| owned_str(id))
+        }
+    }
+    /**
+     * Gets the system identifier
+     */
+    pub fn system_id(&self) -> Option<~str> {
+        unsafe {
+            ptr_to_option(self.dtd.systemId).map(|id| owned_str(id))
+        }
+    }
+    /**
+     * Iterate over element declarations
+     */
+    pub fn element_declarations(&self) -> ElementDeclIterator<'r> {
+        ElementDeclIterator {
+            cur: ptr_to_option(self.dtd.children).map(|cur| unsafe {&*cur})
+        }
+    }
+    /**
+     * Iterate over attribute declarations
+     */
+    pub fn attribute_declarations(&self) -> AttributeDeclIterator<'r> {
+        AttributeDeclIterator {
+            cur: ptr_to_option(self.dtd.children).map(|cur| unsafe {&*cur})
+        }
+    }
+    /**
+     * Iterate over entity declarations
+     */
+    pub fn entity_declarations(&self) -> EntityDeclIterator<'r> {
+        EntityDeclIterator {
+            cur: ptr_to_option(self.dtd.children).map(|cur| unsafe {&*cur})
+        }
+    }
+}
+
+impl<'r> BorrowedElementDecl<'r> {
+    /**
+     * Gets the name of declared element
+     */
+    pub fn name(&self) -> ~str {
+        unsafe {
+            owned_str(self.decl.name as *ffi::xmlChar)
+        }
+    }
+    /**
+     * Gets the prefix of declared element
+     */
+    pub fn prefix(&self) -> Option<~str> {
+        unsafe {
+            ptr_to_option(self.decl.prefix).map(|p| owned_str(p))
+        }
+    }
+    /**
+     * Gets the type of content
+     */
+    pub fn content_type(&self) -> ElementContentType {
+        match self.decl.etype {
+            ffi::Undefined => UndefinedContentType,
+            ffi::Empty => EmptyContentType,
+            ffi::Any => AnyContentType,
+            ffi::Mixed => MixedContentType,
+            ffi::ElementType => ElementsContentType
+        }
+    }
+    /**
+     * Gets the content model. It is present only for mixed and element
+     * content.
+     *
+     * Nested groups of the same kind without occurrence suffix, such as
+     * `(a, (b, c))`, are flattened into `(a, b, c)`.
+     */
+    pub fn content(&self) -> Option<ContentParticle> {
+        ptr_to_option(self.decl.content).map(|content| unsafe {content_to_particle(&*content)})
+    }
+}
+
+impl<'r> BorrowedAttributeDecl<'r> {
+    /**
+     * Gets the name of declared attribute
+     */
+    pub fn name(&self) -> ~str {
+        unsafe {
+            owned_str(self.decl.name as *ffi::xmlChar)
+        }
+    }
+    /**
+     * Gets the prefix of declared attribute
+     */
+    pub fn prefix(&self) -> Option<~str> {
+        unsafe {
+            ptr_to_option(self.decl.prefix).map(|p| owned_str(p))
+        }
+    }
+    /**
+     * Gets the name of element the attribute is declared on
+     */
+    pub fn element_name(&self) -> ~str {
+        unsafe {
+            owned_str(self.decl.elem)
+        }
+    }
+    /**
+     * Gets the type of attribute
+     */
+    pub fn attr_type(&self) -> AttributeType {
+        attribute_type_from_ffi(self.decl.atype)
+    }
+    /**
+     * Gets the kind of default
+     */
+    pub fn default_kind(&self) -> AttributeDefault {
+        match self.decl.def {
+            ffi::None => ValueDefault,
+            ffi::Required => RequiredDefault,
+            ffi::Implied => ImpliedDefault,
+            ffi::Fixed => FixedDefault
+        }
+    }
+    /**
+     * Gets the default value
+     */
+    pub fn default_value(&self) -> Option<~str> {
+        unsafe {
+            ptr_to_option(self.decl.defaultValue).map(|v| owned_str(v))
+        }
+    }
+    /**
+     * Gets the allowed values of enumeration or notation attribute
+     */
+    pub fn enumeration(&self) -> ~[~str] {
+        let mut values = ~[];
+        let mut cur = self.decl.tree;
+        while std::ptr::is_not_null(cur) {
+            unsafe {
+                values.push(owned_str((*cur).name));
+                cur = (*cur).next;
+            }
+        }
+        values
+    }
+}
+
+impl<'r> BorrowedEntityDecl<'r> {
+    /**
+     * Gets the name of declared entity
+     */
+    pub fn name(&self) -> ~str {
+        unsafe {
+            owned_str(self.decl.name as *ffi::xmlChar)
+        }
+    }
+    /**
+     * Gets the type of entity
+     */
+    pub fn entity_type(&self) -> EntityType {
+        match self.decl.etype {
+            ffi::InternalGeneralEntity => InternalGeneralEntity,
+            ffi::ExternalGeneralParsedEntity => ExternalGeneralParsedEntity,
+            ffi::ExternalGeneralUnparsedEntity => ExternalGeneralUnparsedEntity,
+            ffi::InternalParameterEntity => InternalParameterEntity,
+            ffi::ExternalParameterEntity => ExternalParameterEntity,
+            ffi::InternalPredefinedEntity => InternalPredefinedEntity
+        }
+    }
+    /**
+     * Gets the replacement text of internal entity
+     */
+    pub fn content(&self) -> Option<~str> {
+        unsafe {
+            ptr_to_option(self.decl.content).map(|c| owned_str(c))
+        }
+    }
+    /**
+     * Gets the public identifier of external entity
+     */
+    pub fn public_id(&self) -> Option<~str> {
+        unsafe {
+            ptr_to_option(self.decl.externalID).map(|id| owned_str(id))
+        }
+    }
+    /**
+     * Gets the system identifier of external entity
+     */
+    pub fn system_id(&self) -> Option<~str> {
+        unsafe {
+            ptr_to_option(self.decl.systemID).map(|id| owned_str(id))
+        }
+    }
+    /**
+     * Gets the system identifier of external entity resolved against base
+     */
+    pub fn uri(&self) -> Option<~str> {
+        unsafe {
+            ptr_to_option(self.decl.uri).map(|uri| owned_str(uri))
+        }
+    }
+}
+
+impl<'r> BorrowedElement<'r> {
+    /**
+     * Gets the name without copying
+     */
+    pub fn name_str(&self) -> &'r str {
+        unsafe {
+            borrow_str(self.node.name)
+        }
+    }
+    /**
+     * Gets the name as bytes without copying
+     */
+    pub fn name_bytes(&self) -> &'r [u8] {
+        unsafe {
+            borrow_bytes(self.node.name)
+        }
+    }
+    /**
+     * Iterate over children
+     */
+    pub fn children_iter(&self) -> ElementChildrenIterator<'r> {
+        ElementChildrenIterator {
+            cur: ptr_to_option(self.node.children).map(|cur| unsafe {&*cur}),
+            back: ptr_to_option(self.node.last).map(|back| unsafe {&*back})
+        }
+    }
+    /**
+     * Iterate over children which are elements
+     */
+    pub fn child_elements(&self) -> ChildElementIterator<'r> {
+        ChildElementIterator {children: self.children_iter()}
+    }
+    /**
+     * Iterate over children elements with given local name and namespace
+     * URI (`None` matches elements without namespace)
+     */
+    pub fn child_elements_named(&self, local: &str, ns: Option<&str>) -> NamedChildElementIterator<'r> {
+        NamedChildElementIterator {
+            children: self.child_elements(),
+            name: ElementName {local: local.to_owned(), ns: ns.map(|ns| ns.to_owned())}
+        }
+    }
+    /**
+     * Find the first child element with given local name and namespace URI
+     */
+    pub fn first_child_named(&self, local: &str, ns: Option<&str>) -> Option<BorrowedElement<'r>> {
+        self.child_elements().find(|e| e.has_name(local, ns))
+    }
+    /**
+     * Iterate over descendant elements with given local name and namespace
+     * URI in document order
+     */
+    pub fn elements_by_tag_name_ns(&self, local: &str, ns: Option<&str>) -> NamedDescendantIterator<'r> {
+        NamedDescendantIterator {
+            descendants: self.descendants(),
+            name: ElementName {local: local.to_owned(), ns: ns.map(|ns| ns.to_owned())}
+        }
+    }
+    /**
+     * Check if element has given local name and namespace URI, without
+     * copying the name
+     */
+    pub fn has_name(&self, local: &str, ns: Option<&str>) -> bool {
+        xml_str_eq(self.node.name, local) && match (ptr_to_option(self.node.ns), ns) {
+            (Some(node_ns), Some(ns)) => xml_str_eq(unsafe {(*node_ns).href}, ns),
+            (None, None) => true,
+            _ => false
+        }
+    }
+    /**
+     * Iterate over namespaces declared on this element
+     */
+    pub fn namespace_declarations(&self) -> NamespaceIterator<'r> {
+        NamespaceIterator {
+            cur: ptr_to_option(self.node.nsDef).map(|cur| unsafe {&*cur})
+        }
+    }
+    /**
+     * Find the namespace URI bound to prefix (`None` for the default
+     * namespace) in scope of this element
+     */
+    pub fn lookup_namespace_uri(&self, prefix: Option<&str>) -> Option<~str> {
+        use std::ptr::null;
+        unsafe {
+            let ns = match prefix {
+                Some(prefix) => prefix.with_c_str(|prefix| {
+                    ffi::xmlSearchNs(self.node.doc, self.node, prefix as *ffi::xmlChar)
+                }),
+                None => ffi::xmlSearchNs(self.node.doc, self.node, null())
+            };
+            ptr_to_option(ns).map(|ns| BorrowedNamespace {ns: &*ns}.href())
+        }
+    }
+    /**
+     * Find the prefix bound to namespace URI in scope of this element.
+     * Returns `Some(None)` if the namespace is the default one.
+     */
+    pub fn lookup_prefix(&self, href: &str) -> Option<Option<~str>> {
+        unsafe {
+            let ns = href.with_c_str(|href| {
+                ffi::xmlSearchNsByHref(self.node.doc, self.node, href as *ffi::xmlChar)
+            });
+            ptr_to_option(ns).map(|ns| BorrowedNamespace {ns: &*ns}.prefix())
+        }
+    }
+    /**
+     * Gets all namespaces in scope of this element, one for each prefix
+     */
+    pub fn in_scope_namespaces(&self) -> ~[BorrowedNamespace<'r>] {
+        let mut namespaces = ~[];
+        unsafe {
+            let list = ffi::xmlGetNsList(self.node.doc, self.node);
+            if std::ptr::is_not_null(list) {
+                let mut cur = list;
+                while std::ptr::is_not_null(*cur) {
+                    namespaces.push(BorrowedNamespace {ns: &**cur});
+                    cur = std::ptr::offset(cur, 1);
+                }
+                xml_free(list);
+            }
+        }
+        namespaces
+    }
+    /**
+     * Parse a well-balanced fragment as if it were the content of this
+     * element, so that prefixes in scope of the element can be used. The
+     * parsed nodes are not inserted, but can be with `append_child`.
+     *
+     * The data is decoded in the encoding of the document. Processing
+     * instructions and entity references, which are not substituted, are
+     * rejected anywhere in the fragment, including attribute values, as is
+     * invalid UTF-8.
+     */
+    pub fn parse_fragment(&self, data: &[u8]) -> Result<~[DetachedNode<'r>], ParseError> {
+        use std::ptr::{is_not_null, null};
+        init();
+        unsafe {
+            let mut list = null();
+            ffi::xmlResetLastError();
+            let result = ffi::xmlParseInNodeContext(self.node, data.as_ptr() as *std::libc::c_char,
+                                                    data.len() as std::libc::c_int, PARSE_OPTIONS, &mut list);
+            if result != 0 {
+                if is_not_null(list) {
+                    ffi::xmlFreeNodeList(list);
+                }
+                return Err(last_parse_error());
+            }
+            let mut message = if supported_nodes(list) {
+                None
+            } else {
+                Some("Fragment contains a processing instruction or entity reference")
+            };
+            let mut node = list;
+            while message.is_none() && is_not_null(node) {
+                if !check_utf8(node, RejectInvalidUtf8) {
+                    message = Some("Fragment is not valid UTF-8");
+                }
+                node = (*node).next;
+            }
+            match message {
+                Some(message) => {
+                    ffi::xmlFreeNodeList(list);
+                    return Err(ParseError {message: message.to_owned(), line: 0, column: 0});
+                },
+                None => {}
+            }
+            let mut nodes = ~[];
+            while is_not_null(list) {
+                let node = list;
+                list = (*node).next;
+                ffi::xmlUnlinkNode(node);
+                nodes.push(DetachedNode {node: node, doc: &*self.node.doc});
+            }
+            Ok(nodes)
+        }
+    }
+    /**
+     * Insert the node as the last child, declaring namespaces it uses if
+     * needed. Adjacent text nodes are merged. If the node belongs to other
+     * document it is given back.
+     *
+     * It is unsafe as a text node merged with the last child reallocates
+     * its content. Strings borrowed from the last child must not be used
+     * after the call.
+     */
+    pub unsafe fn append_child(&self, child: DetachedNode<'r>) -> Result<ElementChild<'r>, DetachedNode<'r>> {
+        if child.doc as *ffi::xmlDoc != self.node.doc {
+            return Err(child);
+        }
+        let added = &*ffi::xmlAddChild(self.node, child.take());
+        if is_element(added) {
+            ffi::xmlReconciliateNs(self.node.doc, added);
+        }
+        Ok(element_child(added).unwrap())
+    }
+    /**
+     * Check if this element contains the node.
+     */
+    pub fn is_ancestor_of<T: TreeNode<'r>>(&self, other: &T) -> bool {
+        other.ancestors().any(|ancestor| ancestor == *self)
+    }
+    /**
+     * Gets the text of this element and all its descendants concatenated.
+     */
+    pub fn text_content(&self) -> ~str {
+        unsafe {
+            let content = ffi::xmlNodeGetContent(self.node);
+            if std::ptr::is_null(content) {
+                return ~"";
+            }
+            let result = owned_str(content);
+            xml_free(content);
+            result
+        }
+    }
+    /**
+     * Replaces all children with a single text node.
+     *
+     * Text containing NUL character is rejected.
+     *
+     * It is unsafe as the children are freed. Nodes below this element and
+     * strings borrowed from them must not be used after the call.
+     */
+    pub unsafe fn set_text_content(&self, text: &str) -> Result<(), ParseError> {
+        if text.contains_char('\0') {
+            return Err(nul_error());
+        }
+        text.with_c_str(|text| {
+            let escaped = ffi::xmlEncodeSpecialChars(self.node.doc, text as *ffi::xmlChar);
+            ffi::xmlNodeSetContent(self.node, escaped);
+            xml_free(escaped);
+        });
+        Ok(())
+    }
+    /**
+     * Appends text after the last child, merging it with the last child if
+     * it is a text node. Text containing NUL character is rejected.
+     *
+     * It is unsafe as the content of the last child is reallocated when
+     * merged. Strings borrowed from it must not be used after the call.
+     */
+    pub unsafe fn append_text(&self, text: &str) -> Result<(), ParseError> {
+        if text.contains_char('\0') {
+            return Err(nul_error());
+        }
+        text.with_c_str(|text| ffi::xmlNodeAddContent(self.node, text as *ffi::xmlChar));
+        Ok(())
+    }
+    /**
+     * Make sure every namespace used by this element and its descendants is
+     * declared in scope, adding declarations where needed. It should be
+     * called after moving nodes between elements or documents. Returns the
+     * number of fixed namespaces or `None` on failure.
+     *
+     * It is unsafe as namespaces of the nodes below this element are
+     * rewritten. Namespaces borrowed from them must not be used after the
+     * call.
+     */
+    pub unsafe fn reconcile_namespaces(&self) -> Option<uint> {
+        match ffi::xmlReconciliateNs(self.node.doc, self.node) {
+            fixed if fixed >= 0 => Some(fixed as uint),
+            _ => None
+        }
+    }
+    /**
+     * Iterate over descendants in document order
+     */
+    pub fn descendants(&self) -> DescendantIterator<'r> {
+        DescendantIterator {walker: self.walk()}
+    }
+    /**
+     * Iterate over descendants, children before their parents
+     */
+    pub fn descendants_post_order(&self) -> PostOrderIterator<'r> {
+        PostOrderIterator {walker: self.walk()}
+    }
+    /**
+     * Walk over descendants
+     */
+    pub fn walk(&self) -> TreeWalker<'r> {
+        TreeWalker {
+            root: self.node,
+            cur: ptr_to_option(self.node.children).map(|cur| unsafe {&*cur}),
+            entering: true,
+            entered: None
+        }
+    }
+    /**
+     * Gets the first child
+     */
+    pub fn first_child(&self) -> Option<ElementChild<'r>> {
+        self.children_iter().next()
+    }
+    /**
+     * Gets the last child
+     */
+    pub fn last_child(&self) -> Option<ElementChild<'r>> {
+        ptr_to_option(self.node.last).and_then(|last| {
+            let last = unsafe {&*last};
+            element_child(last).or_else(|| node_prev_sibling(last))
+        })
+    }
+    /**
+     * Iterate over arguments.
+     */
+    pub fn attribute_iter(&self) -> ElementAttributeIterator<'r> {
+        ElementAttributeIterator {
+            cur: ptr_to_option(self.node.properties).map(|cur| unsafe {&*cur})
+        }
+    }
+}
+
+impl<'r> BorrowedNamespace<'r> {
+    /**
+     * Get the namespace URI
+     */
+    pub fn href(&self) -> ~str {
+        self.href_str().to_owned()
+    }
+    /**
+     * Get the namespace URI without copying
+     */
+    pub fn href_str(&self) -> &'r str {
+        unsafe {
+            borrow_str(self.ns.href)
+        }
+    }
+    /**
+     * Get the namespace URI as bytes without copying
+     */
+    pub fn href_bytes(&self) -> &'r [u8] {
+        unsafe {
+            borrow_bytes(self.ns.href)
+        }
+    }
+    /**
+     * Get the namespace prefix
+     */
+    pub fn prefix(&self) -> Option<~str> {
+        self.prefix_str().map(|p| p.to_owned())
+    }
+    /**
+     * Get the namespace prefix without copying
+     */
+    pub fn prefix_str(&self) -> Option<&'r str> {
+        unsafe {
+            ptr_to_option(self.ns.prefix).map(|p| borrow_str(p))
+        }
+    }
+    /**
+     * Get the namespace prefix as bytes without copying
+     */
+    pub fn prefix_bytes(&self) -> Option<&'r [u8]> {
+        unsafe {
+            ptr_to_option(self.ns.prefix).map(|p| borrow_bytes(p))
+        }
+    }
+}
+
+impl<'r> BorrowedText<'r> {
+    /// Get contents without copying.
+    pub fn content_str(&self) -> &'r str {
+        unsafe {
+            borrow_str(self.node.content)
+        }
+    }
+    /// Get contents as bytes without copying.
+    pub fn content_bytes(&self) -> &'r [u8] {
+        unsafe {
+            borrow_bytes(self.node.content)
+        }
+    }
+}
+
+impl<'r> Clone for NamespaceIterator<'r> {
+    fn clone(&self) -> NamespaceIterator<'r> {
+        NamespaceIterator{cur: self.cur}
+    }
+}
+
+impl<'r> Iterator<BorrowedNamespace<'r>> for NamespaceIterator<'r> {
+    fn next(&mut self) -> Option<BorrowedNamespace<'r>> {
+        self.cur.and_then(|cur| {
+            self.cur = unsafe {ptr_to_option(cur.next).map(|next| &*next)};
+            Some(BorrowedNamespace {ns: cur})
+        })
+    }
+}
+
+impl<'r> Clone for AttributeChildrenIterator<'r> {
+    fn clone(&self) -> AttributeChildrenIterator<'r> {
+        AttributeChildrenIterator{cur: self.cur}
+    }
+}
+
+impl<'r> Iterator<AttributeChild<'r>> for AttributeChildrenIterator<'r> {
+    fn next(&mut self) -> Option<AttributeChild<'r>> {
+        self.cur.and_then(|cur| {
+            self.cur = unsafe {ptr_to_option(cur.next).map(|next| &*next)};
+            match cur._type {
+                ffi::TextNode => Some(TextAttributeChild(BorrowedText {node: cur})),
+                t => {
+                    error!("Unsupported type {}", t.to_str());
+                    self.next()
+                }
+            }
+        })
+    }
+}
+
+impl<'r> Clone for ElementDeclIterator<'r> {
+    fn clone(&self) -> ElementDeclIterator<'r> {
+        ElementDeclIterator{cur: self.cur}
+    }
+}
+
+impl<'r> Iterator<BorrowedElementDecl<'r>> for ElementDeclIterator<'r> {
+    fn next(&mut self) -> Option<BorrowedElementDecl<'r>> {
+        self.cur.and_then(|cur| {
+            self.cur = unsafe {ptr_to_option(cur.next).map(|next| &*next)};
+            match cur._type {
+                ffi::ElementDecl => Some(BorrowedElementDecl {
+                    decl: unsafe {&*(cur as *ffi::xmlNode as *ffi::xmlElement)}
+                }),
+                _ => self.next()
+            }
+        })
+    }
+}
+
+impl<'r> Clone for AttributeDeclIterator<'r> {
+    fn clone(&self) -> AttributeDeclIterator<'r> {
+        AttributeDeclIterator{cur: self.cur}
+    }
+}
+
+impl<'r> Iterator<BorrowedAttributeDecl<'r>> for AttributeDeclIterator<'r> {
+    fn next(&mut self) -> Option<BorrowedAttributeDecl<'r>> {
+        self.cur.and_then(|cur| {
+            self.cur = unsafe {ptr_to_option(cur.next).map(|next| &*next)};
+            match cur._type {
+                ffi::AttributeDecl => Some(BorrowedAttributeDecl {
+                    decl: unsafe {&*(cur as *ffi::xmlNode as *ffi::xmlAttribute)}
+                }),
+                _ => self.next()
+            }
+        })
+    }
+}
+
+impl<'r> Clone for EntityDeclIterator<'r> {
+    fn clone(&self) -> EntityDeclIterator<'r> {
+        EntityDeclIterator{cur: self.cur}
+    }
+}
+
+impl<'r> Iterator<BorrowedEntityDecl<'r>> for EntityDeclIterator<'r> {
+    fn next(&mut self) -> Option<BorrowedEntityDecl<'r>> {
+        self.cur.and_then(|cur| {
+            self.cur = unsafe {ptr_to_option(cur.next).map(|next| &*next)};
+            match cur._type {
+                ffi::EntityDecl => Some(BorrowedEntityDecl {
+                    decl: unsafe {&*(cur as *ffi::xmlNode as *ffi::xmlEntity)}
+                }),
+                _ => self.next()
+            }
+        })
+    }
+}
+
+impl<'r> Clone for ElementChildrenIterator<'r> {
+    fn clone(&self) -> ElementChildrenIterator<'r> {
+        ElementChildrenIterator{cur: self.cur, back: self.back}
+    }
+}
+
+impl<'r> ElementChildrenIterator<'r> {
+    // Marks the iterator as finished once both ends meet.
+    fn check_end(&mut self, node: &'r ffi::xmlNode, other: Option<&'r ffi::xmlNode>) {
+        if other.map_default(true, |other| other as *ffi::xmlNode == node as *ffi::xmlNode) {
+            self.cur = None;
+            self.back = None;
+        }
+    }
+}
+
+impl<'r> Iterator<ElementChild<'r>> for ElementChildrenIterator<'r> {
+     fn next(&mut self) -> Option<ElementChild<'r>> {
+        self.cur.and_then(|cur| {
+            self.check_end(cur, self.back);
+            if self.cur.is_some() {
+                self.cur = unsafe {ptr_to_option(cur.next).map(|next| &*next)};
+            }
+            match element_child(cur) {
+                Some(child) => Some(child),
+                None => {
+                    error!("Unsupported type {}", cur._type.to_str());
+                    self.next()
+                }
+            }
+        })
+    }
+}
+
+impl<'r> DoubleEndedIterator<ElementChild<'r>> for ElementChildrenIterator<'r> {
+     fn next_back(&mut self) -> Option<ElementChild<'r>> {
+        self.back.and_then(|back| {
+            self.check_end(back, self.cur);
+            if self.back.is_some() {
+                self.back = unsafe {ptr_to_option(back.prev).map(|prev| &*prev)};
+            }
+            match element_child(back) {
+                Some(child) => Some(child),
+                None => {
+                    error!("Unsupported type {}", back._type.to_str());
+                    self.next_back()
+                }
+            }
+        })
+    }
+}
+
+impl<'r> Clone for ChildElementIterator<'r> {
+    fn clone(&self) -> ChildElementIterator<'r> {
+        ChildElementIterator {children: self.children.clone()}
+    }
+}
+
+impl<'r> Iterator<BorrowedElement<'r>> for ChildElementIterator<'r> {
+    fn next(&mut self) -> Option<BorrowedElement<'r>> {
+        self.children.next().and_then(|child| {
+            match child {
+                ElementElementChild(e) => Some(e),
+                _ => self.next()
+            }
+        })
     }
 }
 
-#[unsafe_destructor]
-impl Drop for Document {
-    fn drop(&mut self) {
-        unsafe {
-            ffi::xmlFreeDoc(self.doc);
-        }
+impl<'r> DoubleEndedIterator<BorrowedElement<'r>> for ChildElementIterator<'r> {
+    fn next_back(&mut self) -> Option<BorrowedElement<'r>> {
+        self.children.next_back().and_then(|child| {
+            match child {
+                ElementElementChild(e) => Some(e),
+                _ => self.next_back()
+            }
+        })
     }
 }
 
-impl<'r> BorrowedComment<'r> {
-    /// Get comment contents.
-    pub fn comment(&self) -> ~str {
-        unsafe {
-            std::str::raw::from_c_str(self.node.content as *i8)
-        }
+impl<'r> Clone for NamedChildElementIterator<'r> {
+    fn clone(&self) -> NamedChildElementIterator<'r> {
+        NamedChildElementIterator {children: self.children.clone(), name: self.name.clone()}
     }
 }
 
-impl<'r> BorrowedAttribute<'r> {
-    /**
-     * Iterate over children
-     */
-    pub fn children_iter(&self) -> AttributeChildrenIterator<'r> {
-        AttributeChildrenIterator {
-            cur: ptr_to_option(self.attr.children).map(|cur| unsafe {&*cur})
-        }
-    }
-    /**
-     * Gets the value of the attribute
-     */
-    pub fn value(&self) -> ~str {
-        self.children_iter().map(|c| {
-            match c {
-                TextAttributeChild(t) => t.content()
-            }
-        }).to_owned_vec().concat()
+impl<'r> Iterator<BorrowedElement<'r>> for NamedChildElementIterator<'r> {
+    fn next(&mut self) -> Option<BorrowedElement<'r>> {
+        let name = &self.name;
+        self.children.find(|e| e.has_name(name.local, name.ns.as_ref().map(|ns| ns.as_slice())))
     }
 }
 
-impl<'r> BorrowedElement<'r> {
-    /**
-     * Iterate over children
-     */
-    pub fn children_iter(&self) -> ElementChildrenIterator<'r> {
-        ElementChildrenIterator {
-            cur: ptr_to_option(self.node.children).map(|cur| unsafe {&*cur})
-        }
+impl<'r> Clone for NamedDescendantIterator<'r> {
+    fn clone(&self) -> NamedDescendantIterator<'r> {
+        NamedDescendantIterator {descendants: self.descendants.clone(), name: self.name.clone()}
     }
-    /**
-     * Iterate over arguments.
-     */
-    pub fn attribute_iter(&self) -> ElementAttributeIterator<'r> {
-        ElementAttributeIterator {
-            cur: ptr_to_option(self.node.properties).map(|cur| unsafe {&*cur})
-        }
+}
+
+impl<'r> Iterator<BorrowedElement<'r>> for NamedDescendantIterator<'r> {
+    fn next(&mut self) -> Option<BorrowedElement<'r>> {
+        let name = &self.name;
+        self.descendants.filter_map(|child| child.get_element())
+                        .find(|e| e.has_name(name.local, name.ns.as_ref().map(|ns| ns.as_slice())))
     }
 }
 
-impl<'r> BorrowedNamespace<'r> {
+impl<'r> TreeWalker<'r> {
     /**
-     * Get the namespace URI
+     * Skip descendants of the node entered by the last event. The next
+     * event will be leaving that node.
      */
-    pub fn href(&self) -> ~str {
-        unsafe {
-            std::str::raw::from_c_str(self.ns.href as *i8)
-        }
+    pub fn skip_subtree(&mut self) {
+        self.entered.take().map(|entered| {
+            self.cur = Some(entered);
+            self.entering = false;
+        });
     }
-    /**
-     * Get the namespace prefix
-     */
-    pub fn prefix(&self) -> Option<~str> {
-        unsafe {
-            ptr_to_option(self.ns.prefix).map(|p| std::str::raw::from_c_str(p as *i8))
+    // Moves to the node following the given one, which was just left.
+    fn move_after(&mut self, node: &'r ffi::xmlNode) {
+        match ptr_to_option(node.next) {
+            Some(next) => {
+                self.cur = Some(unsafe {&*next});
+                self.entering = true;
+            },
+            None => {
+                let parent = node.parent;
+                if parent == self.root as *ffi::xmlNode || std::ptr::is_null(parent) {
+                    self.cur = None;
+                } else {
+                    self.cur = Some(unsafe {&*parent});
+                    self.entering = false;
+                }
+            }
         }
     }
 }
 
-impl<'r> Clone for AttributeChildrenIterator<'r> {
-    fn clone(&self) -> AttributeChildrenIterator<'r> {
-        AttributeChildrenIterator{cur: self.cur}
+impl<'r> Clone for TreeWalker<'r> {
+    fn clone(&self) -> TreeWalker<'r> {
+        TreeWalker {
+            root: self.root,
+            cur: self.cur,
+            entering: self.entering,
+            entered: self.entered
+        }
     }
 }
 
-impl<'r> Iterator<AttributeChild<'r>> for AttributeChildrenIterator<'r> {
-    fn next(&mut self) -> Option<AttributeChild<'r>> {
+impl<'r> Iterator<WalkEvent<'r>> for TreeWalker<'r> {
+    fn next(&mut self) -> Option<WalkEvent<'r>> {
         self.cur.and_then(|cur| {
-            self.cur = unsafe {ptr_to_option(cur.next).map(|next| &*next)};
-            match cur._type {
-                ffi::TextNode => Some(TextAttributeChild(BorrowedText {node: cur})),
-                t => {
-                    error!("Unsupported type {}", t.to_str());
+            match element_child(cur) {
+                None => {
+                    self.move_after(cur);
                     self.next()
+                },
+                Some(child) => if self.entering {
+                    self.entered = Some(cur);
+                    match (cur._type, ptr_to_option(cur.children)) {
+                        (ffi::ElementNode, Some(first)) => self.cur = Some(unsafe {&*first}),
+                        _ => self.entering = false
+                    }
+                    Some(EnterWalkEvent(child))
+                } else {
+                    self.entered = None;
+                    self.move_after(cur);
+                    Some(LeaveWalkEvent(child))
                 }
             }
         })
     }
 }
 
-impl<'r> Clone for ElementChildrenIterator<'r> {
-    fn clone(&self) -> ElementChildrenIterator<'r> {
-        ElementChildrenIterator{cur: self.cur}
+impl<'r> Clone for DescendantIterator<'r> {
+    fn clone(&self) -> DescendantIterator<'r> {
+        DescendantIterator {walker: self.walker.clone()}
     }
 }
 
-impl<'r> Iterator<ElementChild<'r>> for ElementChildrenIterator<'r> {
-     fn next(&mut self) -> Option<ElementChild<'r>> {
+impl<'r> Iterator<ElementChild<'r>> for DescendantIterator<'r> {
+    fn next(&mut self) -> Option<ElementChild<'r>> {
+        self.walker.next().and_then(|event| {
+            match event {
+                EnterWalkEvent(child) => Some(child),
+                LeaveWalkEvent(_) => self.next()
+            }
+        })
+    }
+}
+
+impl<'r> Clone for PostOrderIterator<'r> {
+    fn clone(&self) -> PostOrderIterator<'r> {
+        PostOrderIterator {walker: self.walker.clone()}
+    }
+}
+
+impl<'r> Iterator<ElementChild<'r>> for PostOrderIterator<'r> {
+    fn next(&mut self) -> Option<ElementChild<'r>> {
+        self.walker.next().and_then(|event| {
+            match event {
+                EnterWalkEvent(_) => self.next(),
+                LeaveWalkEvent(child) => Some(child)
+            }
+        })
+    }
+}
+
+impl<'r> Clone for AncestorIterator<'r> {
+    fn clone(&self) -> AncestorIterator<'r> {
+        AncestorIterator{cur: self.cur}
+    }
+}
+
+impl<'r> Iterator<BorrowedElement<'r>> for AncestorIterator<'r> {
+    fn next(&mut self) -> Option<BorrowedElement<'r>> {
         self.cur.and_then(|cur| {
-            self.cur = unsafe {ptr_to_option(cur.next).map(|next| &*next)};
             match cur._type {
-                ffi::ElementNode => Some(ElementElementChild(BorrowedElement {node: cur})),
-                ffi::TextNode => Some(TextElementChild(BorrowedText {node: cur})),
-                ffi::CDataSectionNode => Some(CDataElementChild(BorrowedCData {node: cur})),
-                ffi::CommentNode => Some(CommentElementChild(BorrowedComment {node: cur})),
-                t => {
-                    error!("Unsupported type {}", t.to_str());
-                    self.next()
+                ffi::ElementNode => {
+                    self.cur = unsafe {ptr_to_option(cur.parent).map(|parent| &*parent)};
+                    Some(BorrowedElement {node: cur})
+                },
+                _ => {
+                    self.cur = None;
+                    None
                 }
             }
         })
@@ -394,6 +2340,14 @@ impl<'r> AttributeChild<'r> {
 }
 
 impl<'r> ElementChild<'r> {
+    fn node(&self) -> *ffi::xmlNode {
+        match *self {
+            ElementElementChild(e) => e.node as *ffi::xmlNode,
+            TextElementChild(t) => t.node as *ffi::xmlNode,
+            CDataElementChild(cd) => cd.node as *ffi::xmlNode,
+            CommentElementChild(c) => c.node as *ffi::xmlNode
+        }
+    }
     /// Check if child is an element.
     pub fn is_element(self) -> bool {
         match (self) {
@@ -450,13 +2404,25 @@ impl<'r> ElementChild<'r> {
             _ => None
         }
     }
+
+    /**
+     * Serialize the child as it would appear in the document.
+     */
+    pub fn to_string(self) -> ~str {
+        unsafe {
+            let node = self.node();
+            let buf = ffi::xmlBufferCreate();
+            ffi::xmlNodeDump(buf, (*node).doc, node, 0, 0);
+            let result = owned_str(ffi::xmlBufferContent(buf));
+            ffi::xmlBufferFree(buf);
+            result
+        }
+    }
 }
 
 impl<'r> NamedNode for BorrowedAttribute<'r> {
     fn name(&self) -> ~str {
-        unsafe {
-            std::str::raw::from_c_str(self.attr.name as *i8)
-        }
+        self.name_str().to_owned()
     }
     fn namespace<'t>(&'t self) -> Option<BorrowedNamespace<'t>> {
         unsafe {
@@ -466,19 +2432,47 @@ impl<'r> NamedNode for BorrowedAttribute<'r> {
 }
 
 
+node_identity!(BorrowedAttribute, attr, ffi::xmlAttr)
+node_identity!(BorrowedCData, node, ffi::xmlNode)
+node_identity!(BorrowedComment, node, ffi::xmlNode)
+node_identity!(BorrowedDocument, doc, ffi::xmlDoc)
+node_identity!(BorrowedDtd, dtd, ffi::xmlDtd)
+node_identity!(BorrowedElement, node, ffi::xmlNode)
+node_identity!(BorrowedNamespace, ns, ffi::xmlNs)
+node_identity!(BorrowedText, node, ffi::xmlNode)
+
+node_order!(BorrowedAttribute, attr, ffi::xmlAttr)
+node_order!(BorrowedCData, node, ffi::xmlNode)
+node_order!(BorrowedComment, node, ffi::xmlNode)
+node_order!(BorrowedElement, node, ffi::xmlNode)
+node_order!(BorrowedText, node, ffi::xmlNode)
+
+impl<'r> Ord for ElementChild<'r> {
+    fn lt(&self, other: &ElementChild<'r>) -> bool {
+        self.cmp(other) == Less
+    }
+}
+
+impl<'r> TotalOrd for ElementChild<'r> {
+    fn cmp(&self, other: &ElementChild<'r>) -> Ordering {
+        document_order(self.node(), other.node())
+    }
+}
+
+tree_node!(BorrowedCData)
+tree_node!(BorrowedComment)
+tree_node!(BorrowedElement)
+tree_node!(BorrowedText)
+
 impl<'r> TextNode for BorrowedCData<'r> {
     fn content(&self) -> ~str {
-        unsafe {
-            std::str::raw::from_c_str(self.node.content as *i8)
-        }
+        self.content_str().to_owned()
     }
 }
 
 impl<'r> NamedNode for BorrowedElement<'r> {
     fn name(&self) -> ~str {
-        unsafe {
-            std::str::raw::from_c_str(self.node.name as *i8)
-        }
+        self.name_str().to_owned()
     }
     fn namespace<'t>(&'t self) -> Option<BorrowedNamespace<'t>> {
         unsafe {
@@ -489,9 +2483,529 @@ impl<'r> NamedNode for BorrowedElement<'r> {
 
 impl<'r> TextNode for BorrowedText<'r> {
     fn content(&self) -> ~str {
-        unsafe {
-            std::str::raw::from_c_str(self.node.content as *i8)
+        self.content_str().to_owned()
+    }
+}
+
+fn element_child<'r>(node: &'r ffi::xmlNode) -> Option<ElementChild<'r>> {
+    match node._type {
+        ffi::ElementNode => Some(ElementElementChild(BorrowedElement {node: node})),
+        ffi::TextNode => Some(TextElementChild(BorrowedText {node: node})),
+        ffi::CDataSectionNode => Some(CDataElementChild(BorrowedCData {node: node})),
+        ffi::CommentNode => Some(CommentElementChild(BorrowedComment {node: node})),
+        _ => None
+    }
+}
+
+fn node_parent<'r>(node: &'r ffi::xmlNode) -> Option<BorrowedElement<'r>> {
+    ptr_to_option(node.parent).and_then(|parent| {
+        let parent = unsafe {&*parent};
+        match parent._type {
+            ffi::ElementNode => Some(BorrowedElement {node: parent}),
+            _ => None
+        }
+    })
+}
+
+fn node_next_sibling<'r>(node: &'r ffi::xmlNode) -> Option<ElementChild<'r>> {
+    ptr_to_option(node.next).and_then(|next| {
+        let next = unsafe {&*next};
+        element_child(next).or_else(|| node_next_sibling(next))
+    })
+}
+
+fn node_prev_sibling<'r>(node: &'r ffi::xmlNode) -> Option<ElementChild<'r>> {
+    ptr_to_option(node.prev).and_then(|prev| {
+        let prev = unsafe {&*prev};
+        element_child(prev).or_else(|| node_prev_sibling(prev))
+    })
+}
+
+fn node_sibling_element<'r>(start: *ffi::xmlNode, step: |&'r ffi::xmlNode| -> *ffi::xmlNode) -> Option<BorrowedElement<'r>> {
+    let mut cur = start;
+    while std::ptr::is_not_null(cur) {
+        let node = unsafe {&*cur};
+        match node._type {
+            ffi::ElementNode => return Some(BorrowedElement {node: node}),
+            _ => cur = step(node)
+        }
+    }
+    None
+}
+
+fn node_line(node: &ffi::xmlNode) -> Option<uint> {
+    match unsafe {ffi::xmlGetLineNo(node)} {
+        line if line > 0 => Some(line as uint),
+        _ => None
+    }
+}
+
+fn element_by_id<'r>(doc: *ffi::xmlDoc, id: &str) -> Option<BorrowedElement<'r>> {
+    unsafe {
+        id.with_c_str(|id| ptr_to_option(ffi::xmlGetID(doc, id as *ffi::xmlChar)))
+            .and_then(|attr| ptr_to_option((*attr).parent))
+            .map(|elem| BorrowedElement {node: &*elem})
+    }
+}
+
+fn attribute_type_from_ffi(atype: ffi::xmlAttributeType) -> AttributeType {
+    match atype {
+        ffi::CDATA => CDataAttribute,
+        ffi::ID => IdAttribute,
+        ffi::IDRef => IdRefAttribute,
+        ffi::IDRefs => IdRefsAttribute,
+        ffi::Entity => EntityAttribute,
+        ffi::Entities => EntitiesAttribute,
+        ffi::NMToken => NmTokenAttribute,
+        ffi::NMTokens => NmTokensAttribute,
+        ffi::Enumeration => EnumerationAttribute,
+        ffi::Notation => NotationAttribute
+    }
+}
+
+fn content_to_particle(content: &ffi::xmlElementContent) -> ContentParticle {
+    let occurrence = match content.ocur {
+        ffi::Once => OnceOccurrence,
+        ffi::Opt => OptOccurrence,
+        ffi::Mult => MultOccurrence,
+        ffi::Plus => PlusOccurrence
+    };
+    match content._type {
+        ffi::PCData => PCDataParticle,
+        ffi::ElementContent => {
+            let name = unsafe {owned_str(content.name)};
+            let name = match ptr_to_option(content.prefix) {
+                Some(prefix) => unsafe {owned_str(prefix)} + ":" + name,
+                None => name
+            };
+            ElementParticle(name, occurrence)
+        },
+        ffi::Seq => {
+            let mut particles = ~[];
+            collect_particles(content, &mut particles);
+            SeqParticle(particles, occurrence)
+        },
+        ffi::Or => {
+            let mut particles = ~[];
+            collect_particles(content, &mut particles);
+            ChoiceParticle(particles, occurrence)
+        }
+    }
+}
+
+// libxml2 keeps groups as binary trees - (a, b, c) is SEQ(a, SEQ(b, c)) -
+// so the chain has to be flattened back.
+fn collect_particles(content: &ffi::xmlElementContent, particles: &mut ~[ContentParticle]) {
+    for &child in [content.first, content.second].iter() {
+        ptr_to_option(child).map(|child| {
+            let child = unsafe {&*child};
+            let same_group = match (content._type, child._type, child.ocur) {
+                (ffi::Seq, ffi::Seq, ffi::Once) => true,
+                (ffi::Or, ffi::Or, ffi::Once) => true,
+                _ => false
+            };
+            if same_group {
+                collect_particles(child, particles);
+            } else {
+                particles.push(content_to_particle(child));
+            }
+        });
+    }
+}
+
+// Prepares libxml2 for use from the current thread. Global state is set up
+// only once, while the error handler, which is thread-local in libxml2, is
+// installed on each call as tasks can move between threads.
+fn init() {
+    use std::unstable::mutex::{Mutex, MUTEX_INIT};
+    static mut LOCK: Mutex = MUTEX_INIT;
+    static mut INITIALIZED: bool = false;
+    unsafe {
+        LOCK.lock();
+        if !INITIALIZED {
+            ffi::xmlCheckVersion(ffi::xmlVersion);
+            ffi::xmlInitParser();
+            resolver::install();
+            catalog::install();
+            INITIALIZED = true;
+        }
+        LOCK.unlock();
+        ffi::xmlSetStructuredErrorFunc(std::ptr::null(), collect_error);
+    }
+}
+
+static PARSE_OPTIONS: std::libc::c_int = 64 | 32 /* No errors or warnings for now */ |
+                                          4194304 /* Line numbers above 65535 */;
+
+// Error of the last parse in current thread.
+fn last_parse_error() -> ParseError {
+    unsafe {
+        ptr_to_option(ffi::xmlGetLastError()).map_default(unknown_parse_error(), |error| parse_error(error))
+    }
+}
+
+unsafe fn parse_error(error: *ffi::xmlError) -> ParseError {
+    ParseError {
+        message: ptr_to_option((*error).message).map_default(~"Unknown error", |message| {
+            owned_str(message as *ffi::xmlChar).trim_right().to_owned()
+        }),
+        line: std::num::max((*error).line, 0) as uint,
+        column: std::num::max((*error).int2, 0) as uint
+    }
+}
+
+// Address of the errors collected by the task
+local_data_key!(ERRORS: uint)
+
+// Handler installed by init. The errors are looked up in the data
+// of the running task instead of the handler context, as the handler is
+// per thread while a task may move between threads during the parse.
+extern "C" fn collect_error(_: *std::libc::c_void, error: *ffi::xmlError) {
+    unsafe {
+        match std::local_data::get(ERRORS, |errors| errors.map(|errors| *errors)) {
+            Some(errors) if (*error).level >= 2 /* XML_ERR_ERROR */ => {
+                let errors = errors as *mut ~[(std::libc::c_int, ParseError)];
+                (*errors).push(((*error).code, parse_error(error)));
+            }
+            _ => {}
+        }
+    }
+}
+
+// Runs f with errors reported by libxml2 collected with their codes instead
+// of ignored. Warnings are not collected.
+fn collect_errors<T>(f: || -> T) -> (T, ~[(std::libc::c_int, ParseError)]) {
+    init();
+    let mut errors = ~[];
+    let previous = std::local_data::pop(ERRORS);
+    std::local_data::set(ERRORS, &mut errors as *mut ~[(std::libc::c_int, ParseError)] as uint);
+    let result = f();
+    std::local_data::pop(ERRORS);
+    previous.map(|previous| std::local_data::set(ERRORS, previous));
+    (result, errors)
+}
+
+fn unknown_parse_error() -> ParseError {
+    ParseError {message: ~"Unknown error", line: 0, column: 0}
+}
+
+// libxml2 would end strings passed to it at the character.
+fn nul_error() -> ParseError {
+    ParseError {message: ~"Text contains NUL character", line: 0, column: 0}
+}
+
+// Nodes from different trees are ordered by address of the tree roots, so
+// the order stays total when documents and unlinked nodes are mixed.
+fn document_order(a: *ffi::xmlNode, b: *ffi::xmlNode) -> Ordering {
+    let (a_root, a_path) = tree_path(a);
+    let (b_root, b_path) = tree_path(b);
+    (a_root as uint, a_path).cmp(&(b_root as uint, b_path))
+}
+
+// Topmost ancestor and position of the node below it. Attributes of an
+// element come before its children.
+fn tree_path(node: *ffi::xmlNode) -> (*ffi::xmlNode, ~[(uint, uint)]) {
+    let mut path = ~[];
+    let mut node = node;
+    unsafe {
+        while std::ptr::is_not_null((*node).parent) {
+            let mut index = 0;
+            let mut prev = (*node).prev;
+            while std::ptr::is_not_null(prev) {
+                index += 1;
+                prev = (*prev).prev;
+            }
+            let kind = match (*node)._type {
+                ffi::AttributeNode => 0,
+                _ => 1
+            };
+            path.push((kind, index));
+            node = (*node).parent;
+        }
+    }
+    path.reverse();
+    (node, path)
+}
+
+fn is_element(node: *ffi::xmlNode) -> bool {
+    match unsafe {(*node)._type} {
+        ffi::ElementNode => true,
+        _ => false
+    }
+}
+
+// Element and all its descendant elements in document order.
+fn collect_elements(root: *ffi::xmlNode) -> ~[*ffi::xmlNode] {
+    fn collect(node: *ffi::xmlNode, elements: &mut ~[*ffi::xmlNode]) {
+        elements.push(node);
+        let mut child = unsafe {(*node).children};
+        while std::ptr::is_not_null(child) {
+            if is_element(child) {
+                collect(child, elements);
+            }
+            child = unsafe {(*child).next};
+        }
+    }
+    let mut elements = ~[];
+    collect(root, &mut elements);
+    elements
+}
+
+unsafe fn unlink_ns(elem: *ffi::xmlNode, ns: *ffi::xmlNs) {
+    let elem = elem as *mut ffi::xmlNode;
+    if (*elem).nsDef == ns {
+        (*elem).nsDef = (*ns).next;
+    } else {
+        let mut prev = (*elem).nsDef as *mut ffi::xmlNs;
+        while (*prev).next != ns {
+            prev = (*prev).next as *mut ffi::xmlNs;
+        }
+        (*prev).next = (*ns).next;
+    }
+}
+
+// Points elements and attributes in subtree using old namespace to new one.
+unsafe fn remap_ns(root: *ffi::xmlNode, old: *ffi::xmlNs, new: *ffi::xmlNs) {
+    for &elem in collect_elements(root).iter() {
+        let elem = elem as *mut ffi::xmlNode;
+        if (*elem).ns == old {
+            (*elem).ns = new;
+        }
+        let mut attr = (*elem).properties as *mut ffi::xmlAttr;
+        while std::ptr::is_not_null(attr) {
+            if (*attr).ns == old {
+                (*attr).ns = new;
+            }
+            attr = (*attr).next as *mut ffi::xmlAttr;
+        }
+    }
+}
+
+// Declares on root the prefixes bound to single URI in whole document.
+unsafe fn hoist_namespaces(doc: *ffi::xmlDoc, root: *ffi::xmlNode) {
+    // Prefixes in order of first declaration, so that the hoisted
+    // declarations are serialized in document order
+    let mut bindings: ~[(~str, Option<~str>)] = ~[];
+    for &elem in collect_elements(root).iter() {
+        let mut ns = (*elem).nsDef;
+        while std::ptr::is_not_null(ns) {
+            // The default namespace is never hoisted as it would capture
+            // elements without namespace
+            if std::ptr::is_not_null((*ns).prefix) {
+                let prefix = owned_str((*ns).prefix);
+                let href = owned_str((*ns).href);
+                match bindings.iter().position(|&(ref seen, _)| *seen == prefix) {
+                    Some(i) => {
+                        if bindings[i].n1_ref().as_ref().map_default(false, |seen| *seen != href) {
+                            bindings[i] = (prefix, None);
+                        }
+                    }
+                    None => bindings.push((prefix, Some(href)))
+                }
+            }
+            ns = (*ns).next;
+        }
+    }
+    for &(ref prefix, ref href) in bindings.iter() {
+        href.as_ref().map(|href| {
+            prefix.with_c_str(|prefix| {
+                if std::ptr::is_null(ffi::xmlSearchNs(doc, root, prefix as *ffi::xmlChar)) {
+                    href.with_c_str(|href| {
+                        ffi::xmlNewNs(root, href as *ffi::xmlChar, prefix as *ffi::xmlChar);
+                    });
+                }
+            });
+        });
+    }
+}
+
+// Removes whitespace-only text from element-only content so that it can be reindented.
+unsafe fn strip_blank_text(node: *ffi::xmlNode) {
+    let mut has_elements = false;
+    let mut child = (*node).children;
+    while !std::ptr::is_null(child) {
+        has_elements = has_elements || is_element(child);
+        child = (*child).next;
+    }
+    child = (*node).children;
+    while !std::ptr::is_null(child) {
+        let next = (*child).next;
+        match (*child)._type {
+            ffi::ElementNode => strip_blank_text(child),
+            ffi::TextNode if has_elements && ffi::xmlIsBlankNode(child) != 0 => {
+                ffi::xmlUnlinkNode(child);
+                ffi::xmlFreeNode(child);
+            }
+            _ => {}
+        }
+        child = next;
+    }
+}
+
+// Compares string owned by libxml2 with Rust one without copying it.
+fn xml_str_eq(ptr: *ffi::xmlChar, s: &str) -> bool {
+    std::ptr::is_not_null(ptr) && unsafe {borrow_bytes(ptr)} == s.as_bytes()
+}
+
+// Checks strings exposed by borrowed accessors in the subtree, repairing
+// them if policy allows. Returns false if an invalid string is rejected.
+// Whether the nodes following in the list, their descendants and values of
+// their attributes are all of types the bindings represent.
+unsafe fn supported_nodes(node: *ffi::xmlNode) -> bool {
+    let mut node = node;
+    while std::ptr::is_not_null(node) {
+        match (*node)._type {
+            ffi::ElementNode => {
+                let mut attr = (*node).properties;
+                while std::ptr::is_not_null(attr) {
+                    if !supported_nodes((*attr).children) {
+                        return false;
+                    }
+                    attr = (*attr).next;
+                }
+                if !supported_nodes((*node).children) {
+                    return false;
+                }
+            },
+            ffi::TextNode | ffi::CDataSectionNode | ffi::CommentNode => {},
+            _ => return false
+        }
+        node = (*node).next;
+    }
+    true
+}
+
+unsafe fn check_utf8(node: *ffi::xmlNode, policy: Utf8Policy) -> bool {
+    unsafe fn check(ptr: *ffi::xmlChar, policy: Utf8Policy, fix: |*ffi::xmlChar|) -> bool {
+        if std::ptr::is_null(ptr) || std::str::is_utf8(borrow_bytes(ptr)) {
+            return true;
+        }
+        match policy {
+            RejectInvalidUtf8 => false,
+            ReplaceInvalidUtf8 => {
+                lossy_utf8(borrow_bytes(ptr)).with_c_str(|fixed| fix(fixed as *ffi::xmlChar));
+                true
+            }
+        }
+    }
+    let valid = match (*node)._type {
+        ffi::ElementNode => {
+            let mut valid = check((*node).name, policy, |fixed| ffi::xmlNodeSetName(node, fixed));
+            let mut ns = (*node).nsDef as *mut ffi::xmlNs;
+            while valid && std::ptr::is_not_null(ns) {
+                valid = check((*ns).href, policy, |fixed| {
+                    xml_free((*ns).href);
+                    (*ns).href = ffi::xmlStrdup(fixed);
+                }) && check((*ns).prefix, policy, |fixed| {
+                    xml_free((*ns).prefix);
+                    (*ns).prefix = ffi::xmlStrdup(fixed);
+                });
+                ns = (*ns).next as *mut ffi::xmlNs;
+            }
+            let mut attr = (*node).properties;
+            while valid && std::ptr::is_not_null(attr) {
+                let attr_node = attr as *ffi::xmlNode;
+                valid = check((*attr).name as *ffi::xmlChar, policy, |fixed| ffi::xmlNodeSetName(attr_node, fixed));
+                let mut child = (*attr).children;
+                while valid && std::ptr::is_not_null(child) {
+                    valid = check_utf8(child, policy);
+                    child = (*child).next;
+                }
+                attr = (*attr).next;
+            }
+            valid
+        },
+        ffi::TextNode | ffi::CDataSectionNode | ffi::CommentNode => {
+            check((*node).content, policy, |fixed| ffi::xmlNodeSetContent(node, fixed))
+        },
+        _ => true
+    };
+    let mut child = (*node).children;
+    let mut valid = valid;
+    match (*node)._type {
+        ffi::ElementNode | ffi::DocumentNode => {
+            while valid && std::ptr::is_not_null(child) {
+                valid = check_utf8(child, policy);
+                child = (*child).next;
+            }
+        },
+        _ => {}
+    }
+    valid
+}
+
+fn lossy_utf8(bytes: &[u8]) -> ~str {
+    let mut result = std::str::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let width = std::str::utf8_char_width(bytes[i]);
+        let chunk = if width > 0 && i + width <= bytes.len() {
+            std::str::from_utf8_opt(bytes.slice(i, i + width))
+        } else {
+            None
+        };
+        match chunk {
+            Some(c) => {
+                result.push_str(c);
+                i += width;
+            },
+            None => {
+                result.push_char('\uFFFD');
+                i += 1;
+            }
+        }
+    }
+    result
+}
+
+// The bytes live as long as the node they were taken from.
+unsafe fn borrow_bytes<'r>(ptr: *ffi::xmlChar) -> &'r [u8] {
+    std::cast::transmute(std::unstable::raw::Slice {
+        data: ptr,
+        len: std::libc::strlen(ptr as *std::libc::c_char) as uint
+    })
+}
+
+// Documents hold only valid UTF-8 (see Utf8Policy), so failure is a bug.
+unsafe fn borrow_str<'r>(ptr: *ffi::xmlChar) -> &'r str {
+    match std::str::from_utf8_opt(borrow_bytes(ptr)) {
+        Some(s) => s,
+        None => fail!("libxml2 returned string which is not valid UTF-8")
+    }
+}
+
+// Frees memory allocated by libxml2, which may use a custom allocator.
+unsafe fn xml_free<T>(ptr: *T) {
+    (ffi::xmlFree)(ptr as *std::libc::c_void)
+}
+
+// Strings not covered by the check when reading are converted lossily.
+unsafe fn owned_str(ptr: *ffi::xmlChar) -> ~str {
+    lossy_utf8(borrow_bytes(ptr))
+}
+
+// Resolves the URI against the base as libxml2 does for xml:base.
+fn resolve_uri(uri: &str, base: &str) -> ~str {
+    uri.with_c_str(|uri_ptr| base.with_c_str(|base_ptr| unsafe {
+        let resolved = ffi::xmlBuildURI(uri_ptr as *ffi::xmlChar, base_ptr as *ffi::xmlChar);
+        if std::ptr::is_null(resolved) {
+            uri.to_owned()
+        } else {
+            let owned = owned_str(resolved);
+            xml_free(resolved);
+            owned
         }
+    }))
+}
+
+// Path of URI referring to local file.
+fn uri_to_path(uri: &str) -> Option<Path> {
+    if uri.starts_with("file://") {
+        Some(Path::new(uri.slice_from(7)))
+    } else if uri.contains("://") {
+        None
+    } else {
+        Some(Path::new(uri))
     }
 }
 
@@ -0,0 +1,321 @@
+/*
+ * Copyright (C) 2014 Maciej Piechotka
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+ * THE SOFTWARE.
+ */
+
+// Application of XML patch (RFC 5261) operations to a document.
+
+use std::ptr::{is_null, null};
+use super::{BorrowedElement, CommentElementChild, NamedNode, PatchError};
+use ffi;
+
+/**
+ * Apply operations of the diff element to the document, stopping at the
+ * first failure.
+ */
+pub fn apply(doc: *ffi::xmlDoc, diff: &BorrowedElement) -> Result<(), PatchError> {
+    if diff.name_str() != "diff" {
+        return Err(PatchError {message: ~"Root element of patch is not diff", selector: ~""});
+    }
+    for operation in diff.child_elements() {
+        let selector = attribute(&operation, "sel").unwrap_or(~"");
+        let result = unsafe {
+            match select(doc, &operation, selector) {
+                Err(message) => Err(message),
+                Ok(target) => match operation.name_str() {
+                    "add" => add(doc, &operation, target),
+                    "replace" => replace(doc, &operation, target),
+                    "remove" => remove(&operation, target),
+                    other => Err(format!("Unknown operation {}", other))
+                }
+            }
+        };
+        match result {
+            Ok(()) => {},
+            Err(message) => return Err(PatchError {message: message, selector: selector})
+        }
+    }
+    Ok(())
+}
+
+// Finds the single node matched by selector
+unsafe fn select(doc: *ffi::xmlDoc, operation: &BorrowedElement, selector: &str) -> Result<*ffi::xmlNode, ~str> {
+    if selector.is_empty() {
+        return Err(~"Missing selector");
+    }
+    let ctxt = ffi::xmlXPathNewContext(doc);
+    if is_null(ctxt) {
+        fail!("Failed to allocate XPath context");
+    }
+    for ns in operation.in_scope_namespaces().iter() {
+        match ns.prefix() {
+            Some(prefix) => prefix.with_c_str(|prefix| ns.href().with_c_str(|href| {
+                ffi::xmlXPathRegisterNs(ctxt, prefix as *ffi::xmlChar, href as *ffi::xmlChar);
+            })),
+            None => {}
+        }
+    }
+    let result = selector.with_c_str(|selector| ffi::xmlXPathEvalExpression(selector as *ffi::xmlChar, ctxt));
+    ffi::xmlXPathFreeContext(ctxt);
+    if is_null(result) {
+        return Err(~"Invalid selector");
+    }
+    let nodes = (*result).nodesetval;
+    let found = if (*result).otype != 1 || is_null(nodes) || (*nodes).nodeNr == 0 {
+        Err(~"Selector matches no node")
+    } else if (*nodes).nodeNr > 1 {
+        Err(~"Selector matches more than one node")
+    } else {
+        let node = *(*nodes).nodeTab;
+        match (*node)._type {
+            ffi::NamespaceDecl => Err(~"Namespace nodes cannot be selected"),
+            _ => Ok(node)
+        }
+    };
+    ffi::xmlXPathFreeObject(result);
+    found
+}
+
+unsafe fn add(doc: *ffi::xmlDoc, operation: &BorrowedElement, target: *ffi::xmlNode) -> Result<(), ~str> {
+    match attribute(operation, "type") {
+        Some(node_type) => {
+            match (*target)._type {
+                ffi::ElementNode => {},
+                _ => return Err(~"Target is not an element")
+            }
+            let value = operation.text_content();
+            if node_type.starts_with("@") {
+                let qname = node_type.slice_from(1);
+                let (prefix, local) = match qname.find(':') {
+                    Some(idx) => (Some(qname.slice_to(idx)), qname.slice_from(idx + 1)),
+                    None => (None, qname)
+                };
+                let href = match prefix {
+                    Some(prefix) => match operation.lookup_namespace_uri(Some(prefix)) {
+                        Some(href) => Some(href),
+                        None => return Err(format!("Unknown prefix {}", prefix))
+                    },
+                    None => None
+                };
+                let exists = local.with_c_str(|local| with_opt_c_str(href.as_ref().map(|href| href.as_slice()), |href| {
+                    !is_null(ffi::xmlHasNsProp(target, local as *ffi::xmlChar, href))
+                }));
+                if exists {
+                    return Err(format!("Attribute {} already exists", qname));
+                }
+                let ns = match href {
+                    Some(ref href) => href.with_c_str(|href| {
+                        let ns = ffi::xmlSearchNsByHref(doc, target, href as *ffi::xmlChar);
+                        if is_null(ns) {
+                            with_opt_c_str(prefix, |prefix| ffi::xmlNewNs(target, href as *ffi::xmlChar, prefix))
+                        } else {
+                            ns
+                        }
+                    }),
+                    None => null()
+                };
+                local.with_c_str(|local| value.with_c_str(|value| {
+                    ffi::xmlSetNsProp(target, ns, local as *ffi::xmlChar, value as *ffi::xmlChar);
+                }));
+                Ok(())
+            } else if node_type.starts_with("namespace::") {
+                let prefix = node_type.slice_from(11);
+                let ns = prefix.with_c_str(|prefix| value.with_c_str(|href| {
+                    ffi::xmlNewNs(target, href as *ffi::xmlChar, prefix as *ffi::xmlChar)
+                }));
+                if is_null(ns) {
+                    Err(format!("Namespace {} is already declared", prefix))
+                } else {
+                    Ok(())
+                }
+            } else {
+                Err(format!("Invalid type {}", node_type))
+            }
+        },
+        None => {
+            let pos = attribute(operation, "pos");
+            let sibling = match pos {
+                Some(ref pos) if pos.as_slice() == "before" || pos.as_slice() == "after" => true,
+                Some(ref pos) if pos.as_slice() == "prepend" => false,
+                Some(ref pos) => return Err(format!("Invalid position {}", *pos)),
+                None => false
+            };
+            if sibling && is_null((*target).parent) {
+                return Err(~"Target has no parent");
+            }
+            let into_document = sibling && match (*(*target).parent)._type {
+                ffi::DocumentNode => true,
+                _ => false
+            };
+            let mut nodes = content_nodes(operation);
+            if into_document {
+                for &node in nodes.iter() {
+                    match (*node)._type {
+                        ffi::CommentNode | ffi::PINode => {},
+                        ffi::TextNode if is_whitespace(node) => {},
+                        ffi::ElementNode => return Err(~"Cannot add a sibling element to the root element"),
+                        _ => return Err(~"Only comments and processing instructions can be siblings of the root element")
+                    }
+                }
+                // Whitespace outside of the root element is not kept
+                nodes.retain(|&node| !is_whitespace(node));
+            }
+            if !sibling && match (*target)._type {ffi::ElementNode => false, _ => true} {
+                return Err(~"Target is not an element");
+            }
+            let mut anchor = target;
+            let first = (*target).children;
+            for &node in nodes.iter() {
+                let copy = ffi::xmlDocCopyNode(node, doc, 1);
+                anchor = match pos.as_ref().map(|pos| pos.as_slice()) {
+                    Some("before") => ffi::xmlAddPrevSibling(target, copy),
+                    Some("after") => ffi::xmlAddNextSibling(anchor, copy),
+                    Some(_) if !is_null(first) => ffi::xmlAddPrevSibling(first, copy),
+                    _ => ffi::xmlAddChild(target, copy)
+                };
+            }
+            Ok(())
+        }
+    }
+}
+
+unsafe fn replace(doc: *ffi::xmlDoc, operation: &BorrowedElement, target: *ffi::xmlNode) -> Result<(), ~str> {
+    match (*target)._type {
+        ffi::ElementNode => {
+            let elements = operation.child_elements().to_owned_vec();
+            if elements.len() != 1 {
+                return Err(~"Replacement of element must be a single element");
+            }
+            let copy = ffi::xmlDocCopyNode(elements[0].node as *ffi::xmlNode, doc, 1);
+            ffi::xmlReplaceNode(target, copy);
+            ffi::xmlFreeNode(target);
+            Ok(())
+        },
+        ffi::AttributeNode | ffi::TextNode | ffi::CDataSectionNode => {
+            set_content(target, operation.text_content());
+            Ok(())
+        },
+        ffi::CommentNode => {
+            match operation.children_iter().find(|child| child.is_comment()) {
+                Some(CommentElementChild(comment)) => {
+                    set_content(target, comment.comment());
+                    Ok(())
+                },
+                _ => Err(~"Replacement of comment must be a comment")
+            }
+        },
+        ffi::PINode => {
+            let nodes = content_nodes(operation);
+            match nodes.move_iter().find(|&node| match (*node)._type {ffi::PINode => true, _ => false}) {
+                Some(pi) => {
+                    let copy = ffi::xmlDocCopyNode(pi, doc, 1);
+                    ffi::xmlReplaceNode(target, copy);
+                    ffi::xmlFreeNode(target);
+                    Ok(())
+                },
+                None => Err(~"Replacement of processing instruction must be a processing instruction")
+            }
+        },
+        _ => Err(~"Unsupported target node")
+    }
+}
+
+unsafe fn remove(operation: &BorrowedElement, target: *ffi::xmlNode) -> Result<(), ~str> {
+    match (*target)._type {
+        ffi::AttributeNode => {
+            ffi::xmlRemoveProp(target as *ffi::xmlAttr);
+            return Ok(());
+        },
+        ffi::ElementNode | ffi::TextNode | ffi::CDataSectionNode | ffi::CommentNode | ffi::PINode => {},
+        _ => return Err(~"Unsupported target node")
+    }
+    if !is_null((*target).parent) && match (*(*target).parent)._type {ffi::DocumentNode => true, _ => false} {
+        match (*target)._type {
+            ffi::ElementNode => return Err(~"Cannot remove the root element"),
+            _ => {}
+        }
+    }
+    let (before, after) = match attribute(operation, "ws") {
+        None => (false, false),
+        Some(ws) => match ws.as_slice() {
+            "before" => (true, false),
+            "after" => (false, true),
+            "both" => (true, true),
+            other => return Err(format!("Invalid ws {}", other))
+        }
+    };
+    let (prev, next) = ((*target).prev, (*target).next);
+    if (before && !is_whitespace(prev)) || (after && !is_whitespace(next)) {
+        return Err(~"No whitespace to remove");
+    }
+    for &(wanted, node) in [(before, prev), (after, next), (true, target)].iter() {
+        if wanted {
+            ffi::xmlUnlinkNode(node);
+            ffi::xmlFreeNode(node);
+        }
+    }
+    Ok(())
+}
+
+// Child nodes of the operation which can be added to the document
+unsafe fn content_nodes(operation: &BorrowedElement) -> ~[*ffi::xmlNode] {
+    let mut nodes = ~[];
+    let mut child = operation.node.children;
+    while !is_null(child) {
+        match (*child)._type {
+            ffi::ElementNode | ffi::TextNode | ffi::CDataSectionNode | ffi::CommentNode | ffi::PINode => nodes.push(child),
+            _ => {}
+        }
+        child = (*child).next;
+    }
+    nodes
+}
+
+unsafe fn is_whitespace(node: *ffi::xmlNode) -> bool {
+    !is_null(node) && match (*node)._type {
+        ffi::TextNode => is_null((*node).content) || super::owned_str((*node).content).trim().is_empty(),
+        _ => false
+    }
+}
+
+unsafe fn set_content(node: *ffi::xmlNode, content: ~str) {
+    content.with_c_str(|content| {
+        match (*node)._type {
+            // Content of attributes is parsed for entity references
+            ffi::AttributeNode => {
+                let escaped = ffi::xmlEncodeSpecialChars((*node).doc, content as *ffi::xmlChar);
+                ffi::xmlNodeSetContent(node, escaped);
+                super::xml_free(escaped);
+            },
+            _ => ffi::xmlNodeSetContent(node, content as *ffi::xmlChar)
+        }
+    })
+}
+
+fn attribute(element: &BorrowedElement, name: &str) -> Option<~str> {
+    element.attribute_iter().find(|attr| attr.name_str() == name && attr.namespace().is_none()).map(|attr| attr.value())
+}
+
+fn with_opt_c_str<T>(s: Option<&str>, f: |*ffi::xmlChar| -> T) -> T {
+    match s {
+        Some(s) => s.with_c_str(|s| f(s as *ffi::xmlChar)),
+        None => f(null())
+    }
+}
@@ -0,0 +1,171 @@
+/*
+ * Copyright (C) 2014 Maciej Piechotka
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+ * THE SOFTWARE.
+ */
+
+/*!
+ * Resolution of external resources, such as DTDs and entities, referenced
+ * by parsed documents.
+ */
+
+use std::hashmap::HashMap;
+use std::io::{IoError,PermissionDenied,io_error};
+use std::libc::{c_char, c_int};
+use std::local_data;
+use ffi;
+
+/**
+ * Source of the resolved resource.
+ */
+pub enum Resource {
+    /// Resource read to the end from reader
+    ReaderResource(~Reader),
+    /// Resource held in memory
+    BytesResource(~[u8]),
+    /// Loading of resource is not allowed, with the reason
+    DeniedResource(~str)
+}
+
+/**
+ * Resolver of external resources used during parsing instead of
+ * fetching them by libxml2.
+ */
+pub trait ResourceResolver {
+    /**
+     * Resolve the resource by its public identifier and system identifier.
+     * System identifier is already resolved against the base URI of the
+     * document, which is passed for reference.
+     */
+    fn resolve(&self, public_id: Option<&str>, system_id: Option<&str>, base: Option<&str>) -> Resource;
+}
+
+/**
+ * Resolver denying access to all resources.
+ */
+pub struct DenyAllResolver;
+
+impl ResourceResolver for DenyAllResolver {
+    fn resolve(&self, _: Option<&str>, system_id: Option<&str>, _: Option<&str>) -> Resource {
+        DeniedResource(format!("Access to {} denied", system_id.unwrap_or("resource")))
+    }
+}
+
+/**
+ * Resolver serving resources by system identifier from memory and denying
+ * all others.
+ */
+impl ResourceResolver for HashMap<~str, ~[u8]> {
+    fn resolve(&self, public_id: Option<&str>, system_id: Option<&str>, base: Option<&str>) -> Resource {
+        match system_id.and_then(|system_id| self.find(&system_id.to_owned())) {
+            Some(bytes) => BytesResource(bytes.clone()),
+            None => DenyAllResolver.resolve(public_id, system_id, base)
+        }
+    }
+}
+
+struct Context<'r> {
+    resolver: &'r ResourceResolver,
+    base: Option<&'r str>,
+    error: Option<IoError>
+}
+
+// Address of the context of the parse running in current task
+local_data_key!(CONTEXT: uint)
+
+static mut DEFAULT_LOADER: Option<extern "C" fn (url: *c_char, id: *c_char, ctxt: *ffi::xmlParserCtxt) -> *ffi::xmlParserInput> = None;
+
+/**
+ * Install the loader calling resolvers. It has to be called once, under
+ * the initialization lock.
+ */
+pub unsafe fn install() {
+    DEFAULT_LOADER = Some(ffi::xmlGetExternalEntityLoader());
+    ffi::xmlSetExternalEntityLoader(load);
+}
+
+/**
+ * Run the parse with resolver, returning its result and the error of
+ * loading a resource, if any.
+ */
+pub fn with_resolver<T>(resolver: &ResourceResolver, base: Option<&str>, parse: || -> T) -> (T, Option<IoError>) {
+    let mut context = Context {
+        resolver: resolver,
+        base: base,
+        error: None
+    };
+    let previous = local_data::pop(CONTEXT);
+    local_data::set(CONTEXT, &mut context as *mut Context as uint);
+    let result = parse();
+    local_data::pop(CONTEXT);
+    previous.map(|previous| local_data::set(CONTEXT, previous));
+    (result, context.error)
+}
+
+extern "C" fn load(url: *c_char, id: *c_char, ctxt: *ffi::xmlParserCtxt) -> *ffi::xmlParserInput {
+    unsafe {
+        match local_data::get(CONTEXT, |context| context.map(|context| *context)) {
+            None => match DEFAULT_LOADER {
+                Some(loader) => loader(url, id, ctxt),
+                None => std::ptr::null()
+            },
+            Some(context) => {
+                let context = &mut *(context as *mut Context);
+                let public_id = super::ptr_to_option(id as *ffi::xmlChar).map(|id| super::owned_str(id));
+                let system_id = super::ptr_to_option(url as *ffi::xmlChar).map(|url| super::owned_str(url));
+                let resource = context.resolver.resolve(public_id.as_ref().map(|s| s.as_slice()),
+                                                        system_id.as_ref().map(|s| s.as_slice()),
+                                                        context.base);
+                let bytes = match resource {
+                    ReaderResource(mut reader) => {
+                        let mut error = None;
+                        let bytes = io_error::cond.trap(|err| error = Some(err)).inside(|| reader.read_to_end());
+                        match error {
+                            None => Some(bytes),
+                            Some(err) => {
+                                context.error = Some(err);
+                                None
+                            }
+                        }
+                    },
+                    BytesResource(bytes) => Some(bytes),
+                    DeniedResource(reason) => {
+                        context.error = Some(IoError {
+                            kind: PermissionDenied,
+                            desc: "Access to resource denied",
+                            detail: Some(reason)
+                        });
+                        None
+                    }
+                };
+                bytes.map_default(std::ptr::null(), |bytes| {
+                    let buf = ffi::xmlParserInputBufferCreateMem(bytes.as_ptr() as *c_char, bytes.len() as c_int, 0);
+                    let input = ffi::xmlNewIOInputStream(ctxt, buf, 0);
+                    if std::ptr::is_null(input) {
+                        ffi::xmlFreeParserInputBuffer(buf);
+                    } else if std::ptr::is_not_null(url) {
+                        // Relative identifiers in the resource are resolved against it
+                        (*(input as *mut ffi::xmlParserInput)).filename = ffi::xmlStrdup(url as *ffi::xmlChar) as *c_char;
+                    }
+                    input
+                })
+            }
+        }
+    }
+}
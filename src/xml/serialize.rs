@@ -0,0 +1,723 @@
+/*
+ * Copyright (C) 2014 Maciej Piechotka
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+ * THE SOFTWARE.
+ */
+
+/*!
+ * Decoding of values from elements and encoding of values into documents,
+ * using `extra::serialize`.
+ *
+ * A struct maps to an element. A field is read from the attribute of the
+ * same name or from the child elements of the same name, in the namespace
+ * of the parent element or, if unqualified, in no namespace. Scalar fields
+ * are written as attributes and other fields as child elements. Field named
+ * `_text` maps to the text directly inside the element. Vectors map to
+ * repeated child elements and missing optional fields are omitted. Unit enum
+ * variants are written as their name, other variants as a child element
+ * named after variant with arguments in `item` child elements. Maps are
+ * elements with `entry` child element per entry, holding `key` and `value`
+ * child elements.
+ *
+ * Decoding stops at invalid input, reporting the path and line of the
+ * offending element.
+ */
+
+use std::from_str::FromStr;
+use std::util::replace;
+use extra::serialize;
+use super::{BorrowedElement, Document, NamedNode, TextNode, TreeNode};
+use super::{CDataElementChild, TextElementChild};
+use ffi;
+
+/**
+ * Decoder reading values from the element. After the first error it reads
+ * empty values: no sequence items, no optional values and the first enum
+ * variant.
+ */
+pub struct Decoder<'r> {
+    priv stack: ~[Value<'r>],
+    priv root: BorrowedElement<'r>,
+    priv qualified: bool,
+    priv error: Option<DecodeError>
+}
+
+/**
+ * Description of a value which cannot be decoded.
+ */
+#[deriving(Clone, Eq)]
+pub struct DecodeError {
+    /// Description of the problem.
+    message: ~str,
+    /// Path of the offending element or attribute, such as `/order/@number`.
+    path: ~str,
+    /// Line of the offending element, 0 if unknown.
+    line: uint
+}
+
+/**
+ * Encoder writing values into a new document.
+ */
+pub struct Encoder {
+    priv doc: Document,
+    // Namespace of child elements
+    priv ns: *ffi::xmlNs,
+    priv stack: ~[*ffi::xmlNode],
+    priv target: Target,
+    // Entry whose key was written last
+    priv entry: Option<*ffi::xmlNode>
+}
+
+#[deriving(Clone)]
+enum Value<'r> {
+    ElementValue(BorrowedElement<'r>),
+    // Text with its owner element and name, such as attribute
+    TextValue(BorrowedElement<'r>, ~str, ~str),
+    // Child elements matching the field name
+    FieldValue(BorrowedElement<'r>, ~str, ~[BorrowedElement<'r>]),
+    ListValue(~[Value<'r>])
+}
+
+// Where the next value is written
+enum Target {
+    SelfTarget,
+    FieldTarget(~str),
+    ElementTarget(~str)
+}
+
+/**
+ * Decode the value from element, failing the task on invalid input.
+ */
+pub fn decode<'r, T: serialize::Decodable<Decoder<'r>>>(element: BorrowedElement<'r>) -> T {
+    match try_decode(element) {
+        Ok(value) => value,
+        Err(err) => fail!("{}", err.to_str())
+    }
+}
+
+/**
+ * Decode the value from element, reporting the first invalid input.
+ */
+pub fn try_decode<'r, T: serialize::Decodable<Decoder<'r>>>(element: BorrowedElement<'r>) -> Result<T, DecodeError> {
+    decode_with(Decoder::new(element))
+}
+
+/**
+ * Decode the value from element with unqualified child elements.
+ */
+pub fn try_decode_unqualified<'r, T: serialize::Decodable<Decoder<'r>>>(element: BorrowedElement<'r>) -> Result<T, DecodeError> {
+    decode_with(Decoder::new_unqualified(element))
+}
+
+fn decode_with<'r, T: serialize::Decodable<Decoder<'r>>>(mut decoder: Decoder<'r>) -> Result<T, DecodeError> {
+    let value = serialize::Decodable::decode(&mut decoder);
+    match decoder.error {
+        Some(err) => Err(err),
+        None => Ok(value)
+    }
+}
+
+/**
+ * Encode the value as document with the root element of given name and
+ * namespace.
+ */
+pub fn encode<T: serialize::Encodable<Encoder>>(value: &T, root: &str, ns: Option<&str>) -> Document {
+    let mut encoder = Encoder::new(root, ns);
+    value.encode(&mut encoder);
+    encoder.unwrap()
+}
+
+/**
+ * Encode the value as document with unqualified child elements.
+ */
+pub fn encode_unqualified<T: serialize::Encodable<Encoder>>(value: &T, root: &str, ns: Option<&str>) -> Document {
+    let mut encoder = Encoder::new_unqualified(root, ns);
+    value.encode(&mut encoder);
+    encoder.unwrap()
+}
+
+impl ToStr for DecodeError {
+    fn to_str(&self) -> ~str {
+        let line = if self.line == 0 {~"unknown"} else {self.line.to_str()};
+        format!("{} at {} (line {})", self.message, self.path, line)
+    }
+}
+
+impl<'r> Decoder<'r> {
+    pub fn new(element: BorrowedElement<'r>) -> Decoder<'r> {
+        Decoder {
+            stack: ~[ElementValue(element.clone())],
+            root: element,
+            qualified: true,
+            error: None
+        }
+    }
+
+    /**
+     * Create decoder reading child elements in no namespace.
+     */
+    pub fn new_unqualified(element: BorrowedElement<'r>) -> Decoder<'r> {
+        Decoder {
+            qualified: false,
+            ..Decoder::new(element)
+        }
+    }
+
+    /**
+     * Gets the first error met while decoding.
+     */
+    pub fn error<'a>(&'a self) -> Option<&'a DecodeError> {
+        self.error.as_ref()
+    }
+
+    fn top<'a>(&'a self) -> &'a Value<'r> {
+        &self.stack[self.stack.len() - 1]
+    }
+
+    fn with<T>(&mut self, value: Value<'r>, f: |&mut Decoder<'r>| -> T) -> T {
+        self.stack.push(value);
+        let result = f(self);
+        self.stack.pop();
+        result
+    }
+
+    /**
+     * Report invalid value at the current position. Only the first error
+     * is kept.
+     */
+    pub fn report_error(&mut self, message: &str) {
+        if self.error.is_some() {
+            return;
+        }
+        let location = self.stack.rev_iter().filter_map(|value| match *value {
+            ElementValue(ref element) => Some((element.clone(), None)),
+            TextValue(ref element, ref name, _) | FieldValue(ref element, ref name, _) => {
+                Some((element.clone(), Some(name.clone())))
+            },
+            ListValue(_) => None
+        }).next();
+        let (element, name) = location.unwrap_or((self.root.clone(), None));
+        let mut names = element.ancestors().map(|ancestor| ancestor.name()).to_owned_vec();
+        names.reverse();
+        names.push(element.name());
+        name.map(|name| names.push(name));
+        self.error = Some(DecodeError {
+            message: message.to_owned(),
+            path: format!("/{}", names.connect("/")),
+            line: element.line().unwrap_or(0)
+        });
+    }
+
+    fn failed(&self) -> bool {
+        self.error.is_some()
+    }
+
+    fn text(&mut self) -> ~str {
+        let text = match *self.top() {
+            ElementValue(ref element) => Ok(element.text_content()),
+            TextValue(_, _, ref text) => Ok(text.clone()),
+            FieldValue(_, _, ref elements) if !elements.is_empty() => Ok(elements[0].text_content()),
+            FieldValue(..) => Err("Missing value"),
+            ListValue(_) => Err("Expected value")
+        };
+        match text {
+            Ok(text) => text,
+            Err(message) => {
+                self.report_error(message);
+                ~""
+            }
+        }
+    }
+
+    fn element(&mut self) -> BorrowedElement<'r> {
+        let element = match *self.top() {
+            ElementValue(ref element) => Ok(element.clone()),
+            FieldValue(_, _, ref elements) if !elements.is_empty() => Ok(elements[0].clone()),
+            FieldValue(..) => Err("Missing element"),
+            TextValue(..) | ListValue(_) => Err("Expected element")
+        };
+        match element {
+            Ok(element) => element,
+            Err(message) => {
+                self.report_error(message);
+                self.root.clone()
+            }
+        }
+    }
+
+    fn parse<T: FromStr>(&mut self, kind: &str, default: T) -> T {
+        let text = self.text();
+        match from_str(text.trim()) {
+            Some(value) => value,
+            None => {
+                self.report_error(format!("Invalid {} '{}'", kind, text.trim()));
+                default
+            }
+        }
+    }
+
+    fn field(&mut self, name: &str) -> Value<'r> {
+        let element = self.element();
+        if name == "_text" {
+            return TextValue(element.clone(), ~"text()", own_text(&element));
+        }
+        let attr = element.attribute_iter().find(|attr| {
+            attr.name_str() == name && attr.namespace().is_none()
+        });
+        match attr {
+            Some(attr) => TextValue(element.clone(), format!("@{}", name), attr.value()),
+            None => {
+                let ns = if self.qualified {element.namespace().map(|ns| ns.href())} else {None};
+                let children = element.child_elements_named(name, ns.as_ref().map(|ns| ns.as_slice())).to_owned_vec();
+                FieldValue(element.clone(), name.to_owned(), children)
+            }
+        }
+    }
+
+    // Key or value of map entry
+    fn entry_field<T>(&mut self, idx: uint, name: &str, f: |&mut Decoder<'r>| -> T) -> T {
+        let entry = match *self.top() {
+            ListValue(ref items) if idx < items.len() => Some(items[idx].clone()),
+            _ => None
+        };
+        match entry {
+            Some(entry) => self.with(entry, |d| {
+                let value = d.field(name);
+                d.with(value, |d| f(d))
+            }),
+            None => {
+                self.report_error("Expected map");
+                f(self)
+            }
+        }
+    }
+
+    // Positional argument, from child elements or from text of element
+    fn argument<T>(&mut self, idx: uint, f: |&mut Decoder<'r>| -> T) -> T {
+        let element = self.element();
+        let children = element.child_elements().to_owned_vec();
+        if children.is_empty() && idx == 0 {
+            f(self)
+        } else if idx < children.len() {
+            self.with(ElementValue(children[idx].clone()), f)
+        } else {
+            self.report_error(format!("Missing argument {}", idx));
+            f(self)
+        }
+    }
+}
+
+impl<'r> serialize::Decoder for Decoder<'r> {
+    fn read_nil(&mut self) -> () {}
+    fn read_uint(&mut self) -> uint {self.parse("unsigned integer", 0)}
+    fn read_u64(&mut self) -> u64 {self.parse("unsigned integer", 0)}
+    fn read_u32(&mut self) -> u32 {self.parse("unsigned integer", 0)}
+    fn read_u16(&mut self) -> u16 {self.parse("unsigned integer", 0)}
+    fn read_u8(&mut self) -> u8 {self.parse("unsigned integer", 0)}
+    fn read_int(&mut self) -> int {self.parse("integer", 0)}
+    fn read_i64(&mut self) -> i64 {self.parse("integer", 0)}
+    fn read_i32(&mut self) -> i32 {self.parse("integer", 0)}
+    fn read_i16(&mut self) -> i16 {self.parse("integer", 0)}
+    fn read_i8(&mut self) -> i8 {self.parse("integer", 0)}
+    fn read_f64(&mut self) -> f64 {self.parse("number", 0.0)}
+    fn read_f32(&mut self) -> f32 {self.parse("number", 0.0)}
+
+    fn read_bool(&mut self) -> bool {
+        let text = self.text();
+        match text.trim() {
+            "true" | "1" => true,
+            "false" | "0" => false,
+            other => {
+                self.report_error(format!("Invalid boolean '{}'", other));
+                false
+            }
+        }
+    }
+
+    fn read_char(&mut self) -> char {
+        let text = self.text();
+        if text.char_len() == 1 {
+            text.char_at(0)
+        } else {
+            self.report_error(format!("Invalid character '{}'", text));
+            '\0'
+        }
+    }
+
+    fn read_str(&mut self) -> ~str {
+        self.text()
+    }
+
+    fn read_enum<T>(&mut self, _: &str, f: |&mut Decoder<'r>| -> T) -> T {
+        f(self)
+    }
+
+    fn read_enum_variant<T>(&mut self, names: &[&str], f: |&mut Decoder<'r>, uint| -> T) -> T {
+        if self.failed() {
+            return f(self, 0);
+        }
+        let is_text = match *self.top() {
+            TextValue(..) => true,
+            _ => false
+        };
+        let variant = if is_text {
+            None
+        } else {
+            self.element().child_elements().next().and_then(|child| {
+                names.iter().position(|name| *name == child.name_str()).map(|idx| (idx, child))
+            })
+        };
+        match variant {
+            Some((idx, child)) => self.with(ElementValue(child), |d| f(d, idx)),
+            None => {
+                let text = self.text();
+                match names.iter().position(|name| *name == text.trim()) {
+                    Some(idx) => f(self, idx),
+                    None => {
+                        self.report_error(format!("Unknown variant '{}'", text.trim()));
+                        f(self, 0)
+                    }
+                }
+            }
+        }
+    }
+
+    fn read_enum_variant_arg<T>(&mut self, idx: uint, f: |&mut Decoder<'r>| -> T) -> T {
+        self.argument(idx, f)
+    }
+
+    fn read_enum_struct_variant<T>(&mut self, names: &[&str], f: |&mut Decoder<'r>, uint| -> T) -> T {
+        self.read_enum_variant(names, f)
+    }
+
+    fn read_enum_struct_variant_field<T>(&mut self, name: &str, idx: uint, f: |&mut Decoder<'r>| -> T) -> T {
+        self.read_struct_field(name, idx, f)
+    }
+
+    fn read_struct<T>(&mut self, _: &str, _: uint, f: |&mut Decoder<'r>| -> T) -> T {
+        let element = self.element();
+        self.with(ElementValue(element), f)
+    }
+
+    fn read_struct_field<T>(&mut self, name: &str, _: uint, f: |&mut Decoder<'r>| -> T) -> T {
+        let value = self.field(name);
+        self.with(value, f)
+    }
+
+    fn read_tuple<T>(&mut self, f: |&mut Decoder<'r>, uint| -> T) -> T {
+        let element = self.element();
+        let len = element.child_elements().to_owned_vec().len();
+        self.with(ElementValue(element), |d| f(d, len))
+    }
+
+    fn read_tuple_arg<T>(&mut self, idx: uint, f: |&mut Decoder<'r>| -> T) -> T {
+        self.argument(idx, f)
+    }
+
+    fn read_tuple_struct<T>(&mut self, _: &str, f: |&mut Decoder<'r>, uint| -> T) -> T {
+        self.read_tuple(f)
+    }
+
+    fn read_tuple_struct_arg<T>(&mut self, idx: uint, f: |&mut Decoder<'r>| -> T) -> T {
+        self.argument(idx, f)
+    }
+
+    fn read_option<T>(&mut self, f: |&mut Decoder<'r>, bool| -> T) -> T {
+        let present = !self.failed() && match *self.top() {
+            FieldValue(_, _, ref elements) => !elements.is_empty(),
+            _ => true
+        };
+        f(self, present)
+    }
+
+    fn read_seq<T>(&mut self, f: |&mut Decoder<'r>, uint| -> T) -> T {
+        let items = match *self.top() {
+            _ if self.failed() => Some(~[]),
+            ElementValue(ref element) => Some(element.child_elements().map(|child| ElementValue(child)).to_owned_vec()),
+            TextValue(ref element, ref name, ref text) => Some(text.words().map(|word| {
+                TextValue(element.clone(), name.clone(), word.to_owned())
+            }).to_owned_vec()),
+            FieldValue(_, _, ref elements) => Some(elements.iter().map(|element| ElementValue(element.clone())).to_owned_vec()),
+            ListValue(_) => None
+        };
+        let items = match items {
+            Some(items) => items,
+            None => {
+                self.report_error("Expected sequence");
+                ~[]
+            }
+        };
+        let len = items.len();
+        self.with(ListValue(items), |d| f(d, len))
+    }
+
+    fn read_seq_elt<T>(&mut self, idx: uint, f: |&mut Decoder<'r>| -> T) -> T {
+        let item = match *self.top() {
+            ListValue(ref items) if idx < items.len() => Some(items[idx].clone()),
+            _ => None
+        };
+        match item {
+            Some(item) => self.with(item, f),
+            None => {
+                self.report_error("Expected sequence");
+                f(self)
+            }
+        }
+    }
+
+    fn read_map<T>(&mut self, f: |&mut Decoder<'r>, uint| -> T) -> T {
+        let element = self.element();
+        let items = if self.failed() {
+            ~[]
+        } else {
+            element.child_elements().map(|child| ElementValue(child)).to_owned_vec()
+        };
+        let len = items.len();
+        self.with(ListValue(items), |d| f(d, len))
+    }
+
+    fn read_map_elt_key<T>(&mut self, idx: uint, f: |&mut Decoder<'r>| -> T) -> T {
+        self.entry_field(idx, "key", f)
+    }
+
+    fn read_map_elt_val<T>(&mut self, idx: uint, f: |&mut Decoder<'r>| -> T) -> T {
+        self.entry_field(idx, "value", f)
+    }
+}
+
+// Text and CDATA children of the element, without text of its descendants
+fn own_text(element: &BorrowedElement) -> ~str {
+    element.children_iter().filter_map(|child| match child {
+        TextElementChild(text) => Some(text.content()),
+        CDataElementChild(cdata) => Some(cdata.content()),
+        _ => None
+    }).to_owned_vec().concat()
+}
+
+impl Encoder {
+    /**
+     * Create encoder writing into the root element of given name and
+     * namespace.
+     */
+    pub fn new(root: &str, ns: Option<&str>) -> Encoder {
+        Encoder::with_form(root, ns, true)
+    }
+
+    /**
+     * Create encoder writing child elements in no namespace. The namespace
+     * of the root element is bound to prefix `tns`.
+     */
+    pub fn new_unqualified(root: &str, ns: Option<&str>) -> Encoder {
+        Encoder::with_form(root, ns, false)
+    }
+
+    fn with_form(root: &str, ns: Option<&str>, qualified: bool) -> Encoder {
+        use std::ptr::null;
+        super::init();
+        unsafe {
+            let doc = "1.0".with_c_str(|version| ffi::xmlNewDoc(version as *ffi::xmlChar));
+            let node = root.with_c_str(|name| ffi::xmlNewDocNode(doc, null(), name as *ffi::xmlChar, null()));
+            ffi::xmlDocSetRootElement(doc, node);
+            let ns = ns.map_default(null(), |href| href.with_c_str(|href| {
+                // Unqualified children cannot be in scope of default namespace
+                let ns = if qualified {
+                    ffi::xmlNewNs(node, href as *ffi::xmlChar, null())
+                } else {
+                    "tns".with_c_str(|prefix| ffi::xmlNewNs(node, href as *ffi::xmlChar, prefix as *ffi::xmlChar))
+                };
+                ffi::xmlSetNs(node, ns);
+                ns
+            }));
+            Encoder {
+                doc: Document {doc: doc},
+                ns: if qualified {ns} else {null()},
+                stack: ~[node],
+                target: SelfTarget,
+                entry: None
+            }
+        }
+    }
+
+    /**
+     * Take the encoded document.
+     */
+    pub fn unwrap(self) -> Document {
+        self.doc
+    }
+
+    fn current(&self) -> *ffi::xmlNode {
+        self.stack[self.stack.len() - 1]
+    }
+
+    fn new_child(&self, name: &str) -> *ffi::xmlNode {
+        unsafe {
+            let node = name.with_c_str(|name| {
+                ffi::xmlNewDocNode(self.doc.doc, self.ns, name as *ffi::xmlChar, std::ptr::null())
+            });
+            ffi::xmlAddChild(self.current(), node);
+            node
+        }
+    }
+
+    fn with_target(&mut self, target: Target, f: |&mut Encoder|) {
+        let old = replace(&mut self.target, target);
+        f(self);
+        self.target = old;
+    }
+
+    fn with_node(&mut self, node: *ffi::xmlNode, f: |&mut Encoder|) {
+        self.stack.push(node);
+        self.with_target(SelfTarget, f);
+        self.stack.pop();
+    }
+
+    // Runs f inside the element receiving the value
+    fn with_element(&mut self, f: |&mut Encoder|) {
+        let node = match self.target {
+            SelfTarget => self.current(),
+            FieldTarget(ref name) | ElementTarget(ref name) => self.new_child(name.as_slice())
+        };
+        self.with_node(node, f)
+    }
+
+    fn emit_value(&mut self, value: &str) {
+        unsafe {
+            match self.target {
+                SelfTarget => value.with_c_str(|value| {
+                    ffi::xmlNodeAddContent(self.current(), value as *ffi::xmlChar)
+                }),
+                FieldTarget(ref name) => name.with_c_str(|name| value.with_c_str(|value| {
+                    ffi::xmlNewProp(self.current(), name as *ffi::xmlChar, value as *ffi::xmlChar);
+                })),
+                ElementTarget(ref name) => {
+                    let node = self.new_child(name.as_slice());
+                    value.with_c_str(|value| ffi::xmlNodeAddContent(node, value as *ffi::xmlChar))
+                }
+            }
+        }
+    }
+}
+
+impl serialize::Encoder for Encoder {
+    fn emit_nil(&mut self) {}
+    fn emit_uint(&mut self, v: uint) {self.emit_value(v.to_str())}
+    fn emit_u64(&mut self, v: u64) {self.emit_value(v.to_str())}
+    fn emit_u32(&mut self, v: u32) {self.emit_value(v.to_str())}
+    fn emit_u16(&mut self, v: u16) {self.emit_value(v.to_str())}
+    fn emit_u8(&mut self, v: u8) {self.emit_value(v.to_str())}
+    fn emit_int(&mut self, v: int) {self.emit_value(v.to_str())}
+    fn emit_i64(&mut self, v: i64) {self.emit_value(v.to_str())}
+    fn emit_i32(&mut self, v: i32) {self.emit_value(v.to_str())}
+    fn emit_i16(&mut self, v: i16) {self.emit_value(v.to_str())}
+    fn emit_i8(&mut self, v: i8) {self.emit_value(v.to_str())}
+    fn emit_bool(&mut self, v: bool) {self.emit_value(v.to_str())}
+    fn emit_f64(&mut self, v: f64) {self.emit_value(v.to_str())}
+    fn emit_f32(&mut self, v: f32) {self.emit_value(v.to_str())}
+    fn emit_char(&mut self, v: char) {self.emit_value(v.to_str())}
+    fn emit_str(&mut self, v: &str) {self.emit_value(v)}
+
+    fn emit_enum(&mut self, _: &str, f: |&mut Encoder|) {
+        f(self)
+    }
+
+    fn emit_enum_variant(&mut self, name: &str, _: uint, len: uint, f: |&mut Encoder|) {
+        if len == 0 {
+            self.emit_value(name);
+        } else {
+            self.with_element(|e| {
+                let node = e.new_child(name);
+                e.with_node(node, |e| f(e))
+            })
+        }
+    }
+
+    fn emit_enum_variant_arg(&mut self, _: uint, f: |&mut Encoder|) {
+        self.with_target(ElementTarget(~"item"), f)
+    }
+
+    fn emit_enum_struct_variant(&mut self, name: &str, id: uint, len: uint, f: |&mut Encoder|) {
+        self.emit_enum_variant(name, id, len, f)
+    }
+
+    fn emit_enum_struct_variant_field(&mut self, name: &str, idx: uint, f: |&mut Encoder|) {
+        self.emit_struct_field(name, idx, f)
+    }
+
+    fn emit_struct(&mut self, _: &str, _: uint, f: |&mut Encoder|) {
+        self.with_element(f)
+    }
+
+    fn emit_struct_field(&mut self, name: &str, _: uint, f: |&mut Encoder|) {
+        let target = if name == "_text" {SelfTarget} else {FieldTarget(name.to_owned())};
+        self.with_target(target, f)
+    }
+
+    fn emit_tuple(&mut self, _: uint, f: |&mut Encoder|) {
+        self.with_element(f)
+    }
+
+    fn emit_tuple_arg(&mut self, _: uint, f: |&mut Encoder|) {
+        self.with_target(ElementTarget(~"item"), f)
+    }
+
+    fn emit_tuple_struct(&mut self, _: &str, len: uint, f: |&mut Encoder|) {
+        self.emit_tuple(len, f)
+    }
+
+    fn emit_tuple_struct_arg(&mut self, idx: uint, f: |&mut Encoder|) {
+        self.emit_tuple_arg(idx, f)
+    }
+
+    fn emit_option(&mut self, f: |&mut Encoder|) {
+        f(self)
+    }
+
+    fn emit_option_none(&mut self) {}
+
+    fn emit_option_some(&mut self, f: |&mut Encoder|) {
+        f(self)
+    }
+
+    fn emit_seq(&mut self, _: uint, f: |&mut Encoder|) {
+        let name = match self.target {
+            FieldTarget(ref name) | ElementTarget(ref name) => name.clone(),
+            SelfTarget => ~"item"
+        };
+        self.with_target(ElementTarget(name), f)
+    }
+
+    fn emit_seq_elt(&mut self, _: uint, f: |&mut Encoder|) {
+        f(self)
+    }
+
+    fn emit_map(&mut self, _: uint, f: |&mut Encoder|) {
+        self.with_element(f)
+    }
+
+    fn emit_map_elt_key(&mut self, _: uint, f: |&mut Encoder|) {
+        let entry = self.new_child("entry");
+        self.entry = Some(entry);
+        self.with_node(entry, |e| e.with_target(ElementTarget(~"key"), |e| f(e)))
+    }
+
+    fn emit_map_elt_val(&mut self, _: uint, f: |&mut Encoder|) {
+        let entry = self.entry.take().expect("Map key was not written");
+        self.with_node(entry, |e| e.with_target(ElementTarget(~"value"), |e| f(e)))
+    }
+}
@@ -22,8 +22,12 @@
 
 #[feature(macro_rules)];
 
+extern mod extra;
 extern mod xml;
 
+#[path = "test/orders.rs"]
+mod orders;
+
 macro_rules! expect_attribute(
     ($iter:ident, $expected_name:expr, $expected_ns:expr, $expected_value:expr, $attribute_check:expr) => ({
         let next = ($iter).next();
@@ -138,14 +142,48 @@ macro_rules! expect_text(
 
 fn read_memory(xml: &[u8]) -> Option<xml::Document> {
     let mut reader = std::io::mem::BufReader::new(xml);
-    xml::Document::read(&mut reader)
+    xml::Document::read(&mut reader).ok()
+}
+
+// File in the temporary directory, removed when dropped even if the test fails.
+struct TempFile {
+    path: Path
+}
+
+impl TempFile {
+    fn new(extension: &str) -> TempFile {
+        use std::unstable::atomics::{AtomicUint, INIT_ATOMIC_UINT, SeqCst};
+        static mut COUNTER: AtomicUint = INIT_ATOMIC_UINT;
+        let id = unsafe {COUNTER.fetch_add(1, SeqCst)};
+        TempFile {
+            path: std::os::tmpdir().join(format!("xml-rs-test-{}-{}.{}", std::os::getpid(), id, extension))
+        }
+    }
+
+    fn with_content(extension: &str, content: &str) -> TempFile {
+        let file = TempFile::new(extension);
+        std::io::File::create(&file.path).write(content.as_bytes());
+        file
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        std::io::io_error::cond.trap(|_| {}).inside(|| std::io::fs::unlink(&self.path));
+    }
 }
 
 #[test]
 fn test_simple_fail() {
-    let xml = "<?xml version=\"1.0\"> <test />".as_bytes();
-    let doc = read_memory(xml);
-    assert!(doc.is_none());
+    let xml = "<?xml version=\"1.0\">\n <test />".as_bytes();
+    let mut reader = std::io::mem::BufReader::new(xml);
+    match xml::Document::read(&mut reader) {
+        Err(xml::ParseReadError(err)) => {
+            assert_eq!(err.line, 1);
+            assert!(err.message.len() > 0);
+        },
+        _ => fail!("Expected parse error")
+    }
 }
 
 #[test]
@@ -229,12 +267,12 @@ fn test_write() {
     let xml = "<?xml version=\"1.0\"?>\n<h:html xmlns:h=\"http://www.w3.org/TR/html4/\"><head xmlns=\"http://www.w3.org/TR/html4/\"><meta/></head><h:body xml:lang=\"en\"><my:elem/></h:body></h:html>\n".as_bytes();
     let doc = read_memory(xml).unwrap();
     let mut writer = std::io::mem::MemWriter::new();
-    doc.write(&mut writer);
+    assert_eq!(doc.write(&mut writer).ok(), Some(xml.len()));
     assert_eq!(std::str::from_utf8(writer.inner_ref().as_slice()), std::str::from_utf8(xml))
 }
 
 #[test]
-fn test_read_condition() {
+fn test_read_error() {
     use std::io::{BrokenPipe,IoError,io_error};
     struct MyReader<'t> {
         buf: &'t [u8],
@@ -277,35 +315,881 @@ fn test_read_condition() {
     let xml = "<?xml version=\"1.0\"?>\n<h:html xmlns:h=\"http://www.w3.org/TR/html4/\"><head xmlns=\"http://www.w3.org/TR/html4/\">".as_bytes();
     let error = IoError {kind: BrokenPipe, desc: "Test error", detail: None};
     let mut reader = MyReader::new(xml, &error);
-    let mut thrown = false;
-    let trap = io_error::cond.trap(|err| {
-        assert_eq!(err.kind, error.kind);
-        assert_eq!(err.desc, error.desc);
-        assert_eq!(err.detail, error.detail);
-        thrown = true;
-    });
-    trap.inside(|| {
-        xml::Document::read(&mut reader)
-    });
-    assert!(thrown);
+    match xml::Document::read(&mut reader) {
+        Err(xml::IoReadError(err)) => {
+            assert_eq!(err.kind, error.kind);
+            assert_eq!(err.desc, error.desc);
+            assert_eq!(err.detail, error.detail);
+        },
+        _ => fail!("Expected I/O error")
+    }
 }
 
 #[test]
-fn test_write_condition() {
-    use std::io::{OtherIoError,io_error};
+fn test_write_error() {
+    use std::io::OtherIoError;
     let xml = "<?xml version=\"1.0\"?>\n<h:html xmlns:h=\"http://www.w3.org/TR/html4/\"><head xmlns=\"http://www.w3.org/TR/html4/\"><meta/></head><h:body xml:lang=\"en\"><my:elem/></h:body></h:html>\n".as_bytes();
     let doc = read_memory(xml).unwrap();
-    let mut out = std::vec::with_capacity(20);
-    let mut thrown = false;
+    let mut out = [0u8, ..20];
     let size = {
-        let mut writer = std::io::mem::BufWriter::new(out);
-        io_error::cond.trap(|err| {
-            assert_eq!(err.kind, OtherIoError);
-            thrown = true;
-        }).inside(|| {
-            doc.write(&mut writer);
-        });
+        let mut writer = std::io::mem::BufWriter::new(&mut out);
+        match doc.write(&mut writer) {
+            Err(xml::IoWriteError(err)) => assert_eq!(err.kind, OtherIoError),
+            _ => fail!("Expected I/O error")
+        }
         writer.tell() as uint
     };
     assert_eq!(out.slice_to(size), xml.slice_to(size));
 }
+
+#[test]
+fn test_dtd() {
+    use xml::{ElementParticle,SeqParticle,ChoiceParticle,PCDataParticle};
+    use xml::{OnceOccurrence,MultOccurrence};
+    let xml = "<?xml version=\"1.0\"?>
+<!DOCTYPE doc PUBLIC \"-//Test//DTD Doc//EN\" \"doc.dtd\" [
+<!ELEMENT doc (head, (p | note)*)>
+<!ELEMENT p (#PCDATA | em)*>
+<!ELEMENT note EMPTY>
+<!ATTLIST doc lang CDATA #IMPLIED kind (a | b) \"a\">
+<!ENTITY copy \"(c)\">
+]><doc/>".as_bytes();
+    let doc = read_memory(xml).unwrap();
+    assert!(doc.external_subset().is_none());
+    let dtd = doc.internal_subset().unwrap();
+    assert_eq!(dtd.name(), ~"doc");
+    assert_eq!(dtd.public_id(), Some(~"-//Test//DTD Doc//EN"));
+    assert_eq!(dtd.system_id(), Some(~"doc.dtd"));
+    let elements = dtd.element_declarations().to_owned_vec();
+    assert_eq!(elements.len(), 3);
+    assert_eq!(elements[0].name(), ~"doc");
+    assert_eq!(elements[0].content_type(), xml::ElementsContentType);
+    assert_eq!(elements[0].content(), Some(SeqParticle(~[
+        ElementParticle(~"head", OnceOccurrence),
+        ChoiceParticle(~[
+            ElementParticle(~"p", OnceOccurrence),
+            ElementParticle(~"note", OnceOccurrence)
+        ], MultOccurrence)
+    ], OnceOccurrence)));
+    assert_eq!(elements[1].content_type(), xml::MixedContentType);
+    assert_eq!(elements[1].content(), Some(ChoiceParticle(~[
+        PCDataParticle,
+        ElementParticle(~"em", OnceOccurrence)
+    ], MultOccurrence)));
+    assert_eq!(elements[2].content_type(), xml::EmptyContentType);
+    assert_eq!(elements[2].content(), None);
+    let attributes = dtd.attribute_declarations().to_owned_vec();
+    assert_eq!(attributes.len(), 2);
+    assert_eq!(attributes[0].name(), ~"lang");
+    assert_eq!(attributes[0].element_name(), ~"doc");
+    assert_eq!(attributes[0].attr_type(), xml::CDataAttribute);
+    assert_eq!(attributes[0].default_kind(), xml::ImpliedDefault);
+    assert_eq!(attributes[1].attr_type(), xml::EnumerationAttribute);
+    assert_eq!(attributes[1].default_kind(), xml::ValueDefault);
+    assert_eq!(attributes[1].default_value(), Some(~"a"));
+    assert_eq!(attributes[1].enumeration(), ~[~"a", ~"b"]);
+    let entities = dtd.entity_declarations().to_owned_vec();
+    assert_eq!(entities.len(), 1);
+    assert_eq!(entities[0].name(), ~"copy");
+    assert_eq!(entities[0].entity_type(), xml::InternalGeneralEntity);
+    assert_eq!(entities[0].content(), Some(~"(c)"));
+}
+
+#[test]
+fn test_id() {
+    use xml::NamedNode;
+    let xml = "<?xml version=\"1.0\"?>
+<!DOCTYPE doc [
+<!ATTLIST note id ID #REQUIRED>
+<!ATTLIST ref to IDREFS #REQUIRED>
+]><doc><ref to=\"n1 n2 missing\"/><note id=\"n1\"/><note id=\"n2\"/><p xml:id=\"p1\"/></doc>".as_bytes();
+    let doc = read_memory(xml).unwrap();
+    let root = doc.get_root_element().unwrap();
+    let note = doc.get_element_by_id("n2").unwrap();
+    assert_eq!(note.name(), ~"note");
+    assert_eq!(note.attribute_iter().next().unwrap().attr_type(), xml::IdAttribute);
+    assert_eq!(doc.get_element_by_id("p1").unwrap().name(), ~"p");
+    assert!(doc.get_element_by_id("missing").is_none());
+    let reference = root.children_iter().next().unwrap().get_element().unwrap();
+    let to = reference.attribute_iter().next().unwrap();
+    assert_eq!(to.attr_type(), xml::IdRefsAttribute);
+    assert_eq!(to.declaration().unwrap().default_kind(), xml::RequiredDefault);
+    let targets = to.referenced_elements();
+    assert_eq!(targets.len(), 2);
+    assert_eq!(targets[0].attribute_iter().next().unwrap().value(), ~"n1");
+    assert_eq!(targets[1].attribute_iter().next().unwrap().value(), ~"n2");
+}
+
+#[test]
+fn test_navigation() {
+    use xml::{NamedNode,TextNode,TreeNode};
+    let xml = "<?xml version=\"1.0\"?><a x=\"1\"><b/>text<!-- c --><d><e/></d></a>".as_bytes();
+    let doc = read_memory(xml).unwrap();
+    let root = doc.get_root_element().unwrap();
+    assert!(root.parent().is_none());
+    assert!(root.next_sibling().is_none());
+    let b = root.first_child().unwrap().get_element().unwrap();
+    assert_eq!(b.name(), ~"b");
+    assert!(b.prev_sibling().is_none());
+    assert_eq!(b.parent().unwrap().name(), ~"a");
+    let text = b.next_sibling().unwrap().get_text().unwrap();
+    assert_eq!(text.content(), ~"text");
+    assert_eq!(text.prev_element_sibling().unwrap().name(), ~"b");
+    let d = b.next_element_sibling().unwrap();
+    assert_eq!(d.name(), ~"d");
+    assert_eq!(root.last_child().unwrap().get_element().unwrap().name(), ~"d");
+    assert!(d.prev_sibling().unwrap().is_comment());
+    let e = d.first_child().unwrap().get_element().unwrap();
+    let ancestors = e.ancestors().map(|a| a.name()).to_owned_vec();
+    assert_eq!(ancestors, ~[~"d", ~"a"]);
+    // Navigation results borrow the document, so they outlive the node they come from
+    fn grandparent<'r>(e: xml::BorrowedElement<'r>) -> Option<xml::BorrowedElement<'r>> {
+        e.parent().and_then(|parent| parent.parent())
+    }
+    assert_eq!(grandparent(e).unwrap().name(), ~"a");
+    assert_eq!(e.owner_document().get_root_element().unwrap().name(), ~"a");
+    let attr = root.attribute_iter().next().unwrap();
+    assert_eq!(attr.owner_element().name(), ~"a");
+}
+
+#[test]
+fn test_tree_iterators() {
+    use xml::{NamedNode,EnterWalkEvent,LeaveWalkEvent};
+    fn names<'r, T: Iterator<xml::ElementChild<'r>>>(mut iter: T) -> ~[~str] {
+        iter.filter_map(|c| c.get_element()).map(|e| e.name()).to_owned_vec()
+    }
+    let xml = "<?xml version=\"1.0\"?><a><b><c/>t<d/></b><e/><f><g/></f></a>".as_bytes();
+    let doc = read_memory(xml).unwrap();
+    let root = doc.get_root_element().unwrap();
+    assert_eq!(names(root.children_iter().invert()), ~[~"f", ~"e", ~"b"]);
+    let mut iter = root.children_iter();
+    assert_eq!(iter.next().unwrap().get_element().unwrap().name(), ~"b");
+    assert_eq!(iter.next_back().unwrap().get_element().unwrap().name(), ~"f");
+    assert_eq!(iter.next_back().unwrap().get_element().unwrap().name(), ~"e");
+    assert!(iter.next().is_none());
+    assert!(iter.next_back().is_none());
+    assert_eq!(names(root.descendants()), ~[~"b", ~"c", ~"d", ~"e", ~"f", ~"g"]);
+    assert_eq!(names(root.descendants_post_order()), ~[~"c", ~"d", ~"b", ~"e", ~"g", ~"f"]);
+    let mut walker = root.walk();
+    let mut events = ~[];
+    loop {
+        match walker.next() {
+            Some(EnterWalkEvent(child)) => {
+                child.get_element().map(|e| {
+                    events.push(~"+" + e.name());
+                    if e.name() == ~"b" {
+                        walker.skip_subtree();
+                    }
+                });
+            },
+            Some(LeaveWalkEvent(child)) => {
+                child.get_element().map(|e| events.push(~"-" + e.name()));
+            },
+            None => break
+        }
+    }
+    assert_eq!(events, ~[~"+b", ~"-b", ~"+e", ~"-e", ~"+f", ~"+g", ~"-g", ~"-f"]);
+}
+
+#[test]
+fn test_named_elements() {
+    use xml::NamedNode;
+    let xml = "<?xml version=\"1.0\"?><r xmlns:x=\"urn:x\"><a id=\"1\"/>t<x:a id=\"2\"/><b><a id=\"3\"/></b><a id=\"4\"/></r>".as_bytes();
+    let doc = read_memory(xml).unwrap();
+    let root = doc.get_root_element().unwrap();
+    let id = |e: xml::BorrowedElement| e.attribute_iter().next().unwrap().value();
+    assert_eq!(root.child_elements().map(|e| e.name()).to_owned_vec(), ~[~"a", ~"a", ~"b", ~"a"]);
+    assert_eq!(root.child_elements_named("a", None).map(|e| id(e)).to_owned_vec(), ~[~"1", ~"4"]);
+    assert_eq!(root.child_elements_named("a", Some("urn:x")).map(|e| id(e)).to_owned_vec(), ~[~"2"]);
+    assert_eq!(id(root.first_child_named("a", Some("urn:x")).unwrap()), ~"2");
+    assert!(root.first_child_named("c", None).is_none());
+    assert_eq!(root.elements_by_tag_name_ns("a", None).map(|e| id(e)).to_owned_vec(), ~[~"1", ~"3", ~"4"]);
+}
+
+#[test]
+fn test_line() {
+    use xml::TreeNode;
+    let xml = "<?xml version=\"1.0\"?>\n<a>\n<b\n  c=\"d\"/>text<!-- e --></a>".as_bytes();
+    let doc = read_memory(xml).unwrap();
+    assert!(doc.url().is_none());
+    let root = doc.get_root_element().unwrap();
+    assert_eq!(root.line(), Some(2));
+    let b = root.first_child_named("b", None).unwrap();
+    assert_eq!(b.line(), Some(3));
+    assert_eq!(b.attribute_iter().next().unwrap().line(), Some(3));
+    let text = b.next_sibling().unwrap().get_text().unwrap();
+    assert_eq!(text.line(), Some(4));
+    assert_eq!(root.last_child().unwrap().get_comment().unwrap().line(), Some(4));
+}
+
+#[test]
+fn test_big_line() {
+    use xml::TreeNode;
+    let xml = "<?xml version=\"1.0\"?>\n<a>" + "\n".repeat(70000) + "<b/></a>";
+    let doc = read_memory(xml.as_bytes()).unwrap();
+    let b = doc.get_root_element().unwrap().first_child_named("b", None).unwrap();
+    assert_eq!(b.line(), Some(70002));
+}
+
+#[test]
+fn test_namespace_scope() {
+    let xml = "<?xml version=\"1.0\"?><a xmlns=\"urn:d\" xmlns:x=\"urn:x\"><b xmlns:y=\"urn:y\" xmlns:x=\"urn:x2\"/></a>".as_bytes();
+    let doc = read_memory(xml).unwrap();
+    let root = doc.get_root_element().unwrap();
+    let declared = root.namespace_declarations().map(|ns| (ns.prefix(), ns.href())).to_owned_vec();
+    assert_eq!(declared, ~[(None, ~"urn:d"), (Some(~"x"), ~"urn:x")]);
+    let b = root.child_elements().next().unwrap();
+    assert_eq!(b.lookup_namespace_uri(Some("x")), Some(~"urn:x2"));
+    assert_eq!(b.lookup_namespace_uri(None), Some(~"urn:d"));
+    assert_eq!(b.lookup_namespace_uri(Some("z")), None);
+    assert_eq!(b.lookup_prefix("urn:y"), Some(Some(~"y")));
+    assert_eq!(b.lookup_prefix("urn:d"), Some(None));
+    assert_eq!(b.lookup_prefix("urn:x"), None);
+    let mut in_scope = b.in_scope_namespaces().map(|ns| (ns.prefix(), ns.href()));
+    in_scope.sort();
+    assert_eq!(in_scope, ~[(None, ~"urn:d"), (Some(~"x"), ~"urn:x2"), (Some(~"y"), ~"urn:y")]);
+}
+
+#[test]
+fn test_namespace_cleanup() {
+    use std::io::Decorator;
+    fn to_str(doc: &xml::Document) -> ~str {
+        let mut writer = std::io::mem::MemWriter::new();
+        doc.write(&mut writer);
+        std::str::from_utf8(writer.inner_ref().as_slice()).to_owned()
+    }
+    let xml = "<?xml version=\"1.0\"?><a xmlns:z=\"urn:z\"><b xmlns:x=\"urn:x\"><x:c xmlns:x=\"urn:x\"/></b><d xmlns:x=\"urn:x\" x:e=\"1\"/></a>".as_bytes();
+    let mut doc = read_memory(xml).unwrap();
+    assert_eq!(unsafe {doc.get_root_element().unwrap().reconcile_namespaces()}, Some(0));
+    assert_eq!(doc.remove_redundant_namespaces(false), 1);
+    assert_eq!(to_str(&doc), ~"<?xml version=\"1.0\"?>\n<a xmlns:z=\"urn:z\"><b xmlns:x=\"urn:x\"><x:c/></b><d xmlns:x=\"urn:x\" x:e=\"1\"/></a>\n");
+    assert_eq!(doc.remove_redundant_namespaces(true), 2);
+    assert_eq!(to_str(&doc), ~"<?xml version=\"1.0\"?>\n<a xmlns:z=\"urn:z\" xmlns:x=\"urn:x\"><b><x:c/></b><d x:e=\"1\"/></a>\n");
+    assert!(!doc.rename_namespace_prefix("urn:x", "z"));
+    assert!(!doc.rename_namespace_prefix("urn:x", ""));
+    assert!(!doc.rename_namespace_prefix("urn:x", "1y"));
+    assert!(!doc.rename_namespace_prefix("urn:x", "a:b"));
+    assert!(doc.rename_namespace_prefix("urn:x", "y"));
+    assert_eq!(to_str(&doc), ~"<?xml version=\"1.0\"?>\n<a xmlns:z=\"urn:z\" xmlns:y=\"urn:x\"><b><y:c/></b><d y:e=\"1\"/></a>\n");
+    let mut doc = read_memory("<a><b xmlns:q=\"urn:q\"/><c xmlns:p=\"urn:p\"/><d xmlns:q=\"urn:q\"/></a>".as_bytes()).unwrap();
+    assert_eq!(doc.remove_redundant_namespaces(true), 3);
+    assert_eq!(to_str(&doc), ~"<?xml version=\"1.0\"?>\n<a xmlns:q=\"urn:q\" xmlns:p=\"urn:p\"><b/><c/><d/></a>\n");
+}
+
+#[test]
+fn test_borrowed_str() {
+    fn root_name<'r>(doc: &'r xml::Document) -> &'r str {
+        doc.get_root_element().unwrap().name_str()
+    }
+    let xml = "<?xml version=\"1.0\"?><x:a xmlns:x=\"urn:x\" b=\"c\">text<![CDATA[data]]><!--comment--></x:a>".as_bytes();
+    let doc = read_memory(xml).unwrap();
+    assert_eq!(root_name(&doc), "a");
+    let root = doc.get_root_element().unwrap();
+    let ns = root.namespace_declarations().next().unwrap();
+    assert_eq!(ns.href_str(), "urn:x");
+    assert_eq!(ns.prefix_str(), Some("x"));
+    assert_eq!(root.attribute_iter().next().unwrap().name_str(), "b");
+    let mut iter = root.children_iter();
+    assert_eq!(iter.next().unwrap().get_text().unwrap().content_str(), "text");
+    assert_eq!(iter.next().unwrap().get_cdata().unwrap().content_str(), "data");
+    assert_eq!(iter.next().unwrap().get_comment().unwrap().comment_str(), "comment");
+}
+
+#[test]
+fn test_utf8() {
+    let xml = bytes!("<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><a b=\"", 0xe9, "\">", 0xe9, "t", 0xe9, "</a>");
+    let mut reader = std::io::mem::BufReader::new(xml);
+    let doc = xml::Document::read_with_policy(&mut reader, xml::ReplaceInvalidUtf8).unwrap();
+    let root = doc.get_root_element().unwrap();
+    assert_eq!(root.name_bytes(), bytes!("a"));
+    let text = root.first_child().unwrap().get_text().unwrap();
+    assert_eq!(text.content_str(), "été");
+    assert_eq!(text.content_bytes(), bytes!(0xc3, 0xa9, "t", 0xc3, 0xa9));
+    assert_eq!(root.attribute_iter().next().unwrap().value(), ~"é");
+    let invalid = bytes!("<?xml version=\"1.0\" encoding=\"UTF-8\"?><a>", 0xff, "</a>");
+    assert!(read_memory(invalid).is_none());
+    let invalid = bytes!("<?xml version=\"1.0\"?>\n<a b=\"x", 0xff, "y\">", 0xc3, 0xa9, "t", 0xe9, "</a>");
+    let mut reader = std::io::mem::BufReader::new(invalid);
+    assert!(xml::Document::read_with_policy(&mut reader, xml::RejectInvalidUtf8).is_err());
+    let mut reader = std::io::mem::BufReader::new(invalid);
+    let doc = xml::Document::read_with_policy(&mut reader, xml::ReplaceInvalidUtf8).unwrap();
+    let root = doc.get_root_element().unwrap();
+    assert_eq!(root.attribute_iter().next().unwrap().value(), ~"x\uFFFDy");
+    assert_eq!(root.first_child().unwrap().get_text().unwrap().content_str(), "\u00e9t\uFFFD");
+    let malformed = bytes!("<?xml version=\"1.0\"?>\n<a>", 0xff, "</b>");
+    let mut reader = std::io::mem::BufReader::new(malformed);
+    match xml::Document::read_with_policy(&mut reader, xml::ReplaceInvalidUtf8) {
+        Err(xml::ParseReadError(err)) => assert_eq!(err.line, 2),
+        _ => fail!("Expected mismatched tags to be rejected")
+    }
+}
+
+#[test]
+fn test_text_content() {
+    use std::io::Decorator;
+    use xml::TextNode;
+    let xml = "<?xml version=\"1.0\"?>\n<a>x<b>y<![CDATA[z]]></b><!-- c --></a>\n".as_bytes();
+    let doc = read_memory(xml).unwrap();
+    let root = doc.get_root_element().unwrap();
+    assert_eq!(root.text_content(), ~"xyz");
+    unsafe {
+        assert!(root.child_elements().next().unwrap().set_text_content("<&>").is_ok());
+    }
+    assert_eq!(root.text_content(), ~"x<&>");
+    unsafe {
+        assert!(root.append_text(" end").is_ok());
+        assert!(root.append_text("!").is_ok());
+        assert!(root.set_text_content("a\0b").is_err());
+        assert!(root.append_text("\0").is_err());
+    }
+    let last = root.last_child().unwrap().get_text().unwrap();
+    assert_eq!(last.content(), ~" end!");
+    let mut writer = std::io::mem::MemWriter::new();
+    doc.write(&mut writer);
+    assert_eq!(std::str::from_utf8(writer.inner_ref().as_slice()),
+               "<?xml version=\"1.0\"?>\n<a>x<b>&lt;&amp;&gt;</b><!-- c --> end!</a>\n");
+}
+
+#[test]
+fn test_identity_and_order() {
+    use std::hashmap::HashSet;
+    use xml::TreeNode;
+    let xml = "<?xml version=\"1.0\"?><a x=\"1\" y=\"2\"><b><c/></b>t<d/></a>".as_bytes();
+    let doc = read_memory(xml).unwrap();
+    let root = doc.get_root_element().unwrap();
+    let b = root.first_child_named("b", None).unwrap();
+    let c = b.first_child_named("c", None).unwrap();
+    let d = root.first_child_named("d", None).unwrap();
+    assert!(b == root.child_elements().next().unwrap());
+    assert!(b != d);
+    let mut visited = HashSet::new();
+    for e in root.descendants().filter_map(|c| c.get_element()) {
+        visited.insert(e);
+    }
+    assert!(visited.contains(&c));
+    assert!(!visited.contains(&root));
+    assert_eq!(visited.len(), 3);
+    assert!(root < b && b < c && c < d);
+    assert_eq!(d.cmp(&c), Greater);
+    let text = d.prev_sibling().unwrap();
+    assert!(text.is_text());
+    assert!(xml::ElementElementChild(c) < text);
+    let mut attrs = root.attribute_iter().to_owned_vec();
+    attrs.reverse();
+    attrs.sort();
+    assert_eq!(attrs.map(|a| a.value()), ~[~"1", ~"2"]);
+    let other = read_memory(xml).unwrap();
+    let other_root = other.get_root_element().unwrap();
+    let other_b = other_root.first_child_named("b", None).unwrap();
+    let mut mixed = ~[d, other_b, b, other_root, root];
+    mixed.sort();
+    let first = mixed[0] == root;
+    let expected = if first {~[root, b, d, other_root, other_b]} else {~[other_root, other_b, root, b, d]};
+    assert!(mixed == expected);
+    assert!(root.is_ancestor_of(&c));
+    assert!(!b.is_ancestor_of(&d));
+    assert!(!c.is_ancestor_of(&c));
+}
+
+#[test]
+fn test_copy_and_import() {
+    use std::io::Decorator;
+    fn to_str(doc: &xml::Document) -> ~str {
+        let mut writer = std::io::mem::MemWriter::new();
+        doc.write(&mut writer);
+        std::str::from_utf8(writer.inner_ref().as_slice()).to_owned()
+    }
+    let source = read_memory("<?xml version=\"1.0\"?><a xmlns:x=\"urn:x\"><x:b y=\"1\"><c>t</c></x:b></a>".as_bytes()).unwrap();
+    let copy = source.clone();
+    unsafe {
+        // Nothing is borrowed from the copy yet
+        assert!(copy.get_root_element().unwrap().set_text_content("changed").is_ok());
+    }
+    assert_eq!(to_str(&copy), ~"<?xml version=\"1.0\"?>\n<a xmlns:x=\"urn:x\">changed</a>\n");
+    assert_eq!(to_str(&source), ~"<?xml version=\"1.0\"?>\n<a xmlns:x=\"urn:x\"><x:b y=\"1\"><c>t</c></x:b></a>\n");
+    let target = read_memory("<?xml version=\"1.0\"?><r/>".as_bytes()).unwrap();
+    let b = source.get_root_element().unwrap().child_elements().next().unwrap();
+    let root = target.get_root_element().unwrap();
+    unsafe {
+        let deep = target.import_node(&b, true);
+        assert!(root.append_child(deep).is_ok());
+        let shallow = target.import_node(&b, false);
+        assert!(root.append_child(shallow).is_ok());
+        let foreign = source.import_node(&b, true);
+        assert!(root.append_child(foreign).is_err());
+    }
+    assert_eq!(to_str(&target), ~"<?xml version=\"1.0\"?>\n<r><x:b xmlns:x=\"urn:x\" y=\"1\"><c>t</c></x:b><x:b xmlns:x=\"urn:x\" y=\"1\"/></r>\n");
+}
+
+#[test]
+fn test_concurrent() {
+    use std::io::Decorator;
+    fn assert_send<T: Send>(_: &T) {}
+    static TASKS: uint = 16;
+    static ITERATIONS: uint = 50;
+    let xml = "<?xml version=\"1.0\"?>\n<a xmlns:x=\"urn:x\"><x:b c=\"d\">text</x:b><!-- e --></a>\n";
+    let (port, chan) = SharedChan::new();
+    for _ in range(0, TASKS) {
+        let chan = chan.clone();
+        spawn(proc() {
+            for _ in range(0, ITERATIONS) {
+                let doc = read_memory(xml.as_bytes()).unwrap();
+                let mut writer = std::io::mem::MemWriter::new();
+                doc.write(&mut writer);
+                assert_eq!(std::str::from_utf8(writer.inner_ref().as_slice()), xml);
+                assert_send(&doc);
+                chan.send(doc);
+            }
+        });
+    }
+    for _ in range(0, TASKS * ITERATIONS) {
+        let doc: xml::Document = port.recv();
+        assert_eq!(doc.get_root_element().unwrap().text_content(), ~"text");
+    }
+}
+
+#[test]
+fn test_memory_and_path() {
+    let xml = "<?xml version=\"1.0\"?>\n<a>é</a>\n";
+    let doc = xml::Document::from_str(xml).unwrap();
+    assert_eq!(doc.to_string(), xml.to_owned());
+    assert_eq!(doc.to_bytes(), xml.as_bytes().to_owned());
+    let doc = xml::Document::from_bytes(xml.as_bytes()).unwrap();
+    assert_eq!(doc.get_root_element().unwrap().text_content(), ~"é");
+    match xml::Document::from_str("<a>\n</b>") {
+        Err(err) => assert_eq!(err.line, 2),
+        Ok(_) => fail!("Expected parse error")
+    }
+    let file = TempFile::new("xml");
+    let path = file.path.clone();
+    assert_eq!(doc.save_to_path(&path).ok(), Some(xml.len()));
+    let loaded = xml::Document::from_path(&path).unwrap();
+    assert_eq!(loaded.to_string(), xml.to_owned());
+    assert_eq!(loaded.url(), path.as_str().map(|p| p.to_owned()));
+    std::io::fs::unlink(&path);
+    match xml::Document::from_path(&path) {
+        Err(xml::IoReadError(_)) => {},
+        _ => fail!("Expected I/O error")
+    }
+}
+
+#[test]
+fn test_gzip() {
+    use std::io::mem::{BufReader, MemWriter};
+    let xml = "<?xml version=\"1.0\"?>\n<a><b>text</b></a>\n";
+    let mut doc = xml::Document::from_str(xml).unwrap();
+    assert_eq!(doc.compression(), 0);
+    doc.set_compression(12);
+    assert_eq!(doc.compression(), 9);
+    let mut writer = MemWriter::new();
+    let written = doc.write(&mut writer).unwrap();
+    let compressed = writer.inner();
+    assert_eq!(written, compressed.len());
+    assert!(compressed.starts_with([0x1f, 0x8b]));
+    let mut reader = BufReader::new(compressed);
+    let loaded = xml::Document::read(&mut reader).unwrap();
+    assert_eq!(loaded.to_string(), xml.to_owned());
+    let loaded = xml::Document::from_bytes(compressed).unwrap();
+    assert_eq!(loaded.to_string(), xml.to_owned());
+    match xml::Document::from_bytes([0x1f, 0x8b, 0, 1, 2]) {
+        Err(_) => {},
+        Ok(_) => fail!("Expected error on corrupted input")
+    }
+    // Reader which never makes progress
+    struct EmptyReader;
+    impl Reader for EmptyReader {
+        fn read(&mut self, _: &mut [u8]) -> Option<uint> {
+            Some(0)
+        }
+        fn eof(&mut self) -> bool {
+            false
+        }
+    }
+    match xml::Document::read(&mut EmptyReader) {
+        Err(xml::IoReadError(_)) => {},
+        _ => fail!("Expected I/O error on reader without progress")
+    }
+}
+
+#[test]
+fn test_resolver() {
+    use std::hashmap::HashMap;
+    use std::io::PermissionDenied;
+    use std::io::mem::BufReader;
+    use xml::resolver::DenyAllResolver;
+    let xml = "<!DOCTYPE a SYSTEM \"a.dtd\"><a>&greeting;</a>";
+    let mut resources = HashMap::new();
+    resources.insert(~"a.dtd", "<!ENTITY greeting \"hello\">".as_bytes().to_owned());
+    let mut reader = BufReader::new(xml.as_bytes());
+    let doc = xml::Document::read_with_resolver(&mut reader, None, &resources).unwrap();
+    assert_eq!(doc.get_root_element().unwrap().text_content(), ~"hello");
+    let mut reader = BufReader::new(xml.as_bytes());
+    match xml::Document::read_with_resolver(&mut reader, None, &DenyAllResolver) {
+        Err(xml::IoReadError(err)) => assert_eq!(err.kind, PermissionDenied),
+        _ => fail!("Expected access to be denied")
+    }
+    // Relative to the entity referring to it, not to the document
+    let xml = "<!DOCTYPE a SYSTEM \"http://example.com/a.dtd\"><a>&greeting;</a>";
+    let mut resources = HashMap::new();
+    resources.insert(~"http://example.com/a.dtd", "<!ENTITY % b SYSTEM \"x/b.ent\"> %b;".as_bytes().to_owned());
+    resources.insert(~"http://example.com/x/b.ent", "<!ENTITY % c SYSTEM \"c.ent\"> %c;".as_bytes().to_owned());
+    resources.insert(~"http://example.com/x/c.ent", "<!ENTITY greeting \"hello\">".as_bytes().to_owned());
+    let mut reader = BufReader::new(xml.as_bytes());
+    let doc = xml::Document::read_with_resolver(&mut reader, None, &resources).unwrap();
+    assert_eq!(doc.get_root_element().unwrap().text_content(), ~"hello");
+}
+
+#[test]
+fn test_catalog() {
+    use std::io::mem::BufReader;
+    use xml::catalog::Catalog;
+    let dtd = TempFile::with_content("dtd", "<!ENTITY greeting \"hello\">");
+    let uri = format!("file://{}", dtd.path.display());
+    let next = TempFile::with_content("xml",
+        "<catalog xmlns=\"urn:oasis:names:tc:entity:xmlns:xml:catalog\">
+           <group xml:base=\"file:///dtds/\">
+             <rewriteSystem systemIdStartString=\"http://example.com/dtds/\" rewritePrefix=\"local/\"/>
+             <public publicId=\"-//Test//DTD B//EN\" uri=\"b.dtd\"/>
+           </group>
+         </catalog>");
+    let catalog = Catalog::from_str(format!(
+        "<catalog xmlns=\"urn:oasis:names:tc:entity:xmlns:xml:catalog\">
+           <public publicId=\"-//Test//DTD A//EN\" uri=\"{0}\"/>
+           <system systemId=\"http://example.com/a.dtd\" uri=\"{0}\"/>
+           <system systemId=\"http://example.com/c.dtd\" uri=\"c.dtd\"/>
+           <uri name=\"http://example.com/a.xsd\" uri=\"file:///schemas/a.xsd\"/>
+           <nextCatalog catalog=\"file://{1}\"/>
+         </catalog>", uri, next.path.display()).as_slice(), "file:///catalogs/").unwrap();
+    assert_eq!(catalog.resolve_public("-//Test//DTD A//EN"), Some(uri.clone()));
+    assert_eq!(catalog.resolve_public("  -//Test//DTD   A//EN "), Some(uri.clone()));
+    assert_eq!(catalog.resolve_public("urn:publicid:-:Test:DTD+A:EN"), Some(uri.clone()));
+    assert_eq!(catalog.resolve_system("http://example.com/dtds/c.dtd"), Some(~"file:///dtds/local/c.dtd"));
+    assert_eq!(catalog.resolve_public("-//Test//DTD B//EN"), Some(~"file:///dtds/b.dtd"));
+    assert_eq!(catalog.resolve_system("http://example.com/a.dtd"), Some(uri.clone()));
+    assert_eq!(catalog.resolve_system("http://example.com/c.dtd"), Some(~"file:///catalogs/c.dtd"));
+    assert_eq!(catalog.resolve_uri("http://example.com/a.xsd"), Some(~"file:///schemas/a.xsd"));
+    assert_eq!(catalog.resolve_system("http://example.com/b.dtd"), None);
+    let xml = "<!DOCTYPE a PUBLIC \"-//Test//DTD A//EN\" \"http://example.com/b.dtd\"><a>&greeting;</a>";
+    let mut reader = BufReader::new(xml.as_bytes());
+    let doc = xml::Document::read_with_resolver(&mut reader, None, &catalog).unwrap();
+    assert_eq!(doc.get_root_element().unwrap().text_content(), ~"hello");
+    assert!(Catalog::from_str("<a/>", "file:///").is_err());
+    assert!(Catalog::from_path(&next.path).unwrap().resolve_public("-//Test//DTD B//EN").is_some());
+    assert!(Catalog::from_path(&TempFile::new("xml").path).is_err());
+}
+
+#[deriving(Encodable, Decodable, Eq)]
+enum Kind {
+    Small,
+    Large
+}
+
+#[deriving(Encodable, Decodable, Eq)]
+struct Tag {
+    lang: ~str,
+    _text: ~str
+}
+
+#[deriving(Encodable, Decodable, Eq)]
+struct Item {
+    id: uint,
+    kind: Kind,
+    tags: ~[Tag],
+    note: Option<~str>
+}
+
+#[deriving(Encodable, Decodable, Eq)]
+struct Order {
+    number: uint,
+    items: ~[Item]
+}
+
+#[test]
+fn test_serialize() {
+    use std::hashmap::HashMap;
+    use xml::NamedNode;
+    let xml = "<order xmlns=\"urn:orders\" number=\"7\">
+                 <items id=\"1\" kind=\"Small\"><tags lang=\"en\">red</tags><tags lang=\"pl\">czerwony</tags></items>
+                 <items id=\"2\" kind=\"Large\" note=\"fragile\"/>
+               </order>";
+    let expected = Order {
+        number: 7,
+        items: ~[
+            Item {id: 1, kind: Small, tags: ~[Tag {lang: ~"en", _text: ~"red"}, Tag {lang: ~"pl", _text: ~"czerwony"}], note: None},
+            Item {id: 2, kind: Large, tags: ~[], note: Some(~"fragile")}
+        ]
+    };
+    let doc = xml::Document::from_str(xml).unwrap();
+    let order: Order = xml::serialize::decode(doc.get_root_element().unwrap());
+    assert!(order == expected);
+    let encoded = xml::serialize::encode(&order, "order", Some("urn:orders"));
+    let decoded: Order = xml::serialize::decode(encoded.get_root_element().unwrap());
+    assert!(decoded == expected);
+    let unqualified = xml::serialize::encode_unqualified(&order, "order", Some("urn:orders"));
+    let items = unqualified.get_root_element().unwrap().child_elements().next().unwrap();
+    assert!(items.namespace().is_none());
+    let decoded: Order = xml::serialize::try_decode_unqualified(unqualified.get_root_element().unwrap()).unwrap();
+    assert!(decoded == expected);
+    let doc = xml::Document::from_str("<order number=\"7\">\n<items id=\"x\" kind=\"Small\"/><items kind=\"Huge\"/></order>").unwrap();
+    let result: Result<Order, xml::serialize::DecodeError> = xml::serialize::try_decode(doc.get_root_element().unwrap());
+    match result {
+        Err(err) => assert_eq!(err.to_str(), ~"Invalid unsigned integer 'x' at /order/items/@id (line 2)"),
+        Ok(_) => fail!("Expected decoding to fail")
+    }
+    let doc = xml::Document::from_str("<order number=\"7\"><items kind=\"Small\"/></order>").unwrap();
+    let result: Result<Order, xml::serialize::DecodeError> = xml::serialize::try_decode(doc.get_root_element().unwrap());
+    assert_eq!(result.err().map(|err| (err.message, err.path, err.line)),
+               Some((~"Missing value", ~"/order/items/id", 1)));
+    let doc = xml::Document::from_str("<tags lang=\"en\">re<b>not</b>d<![CDATA[!]]></tags>").unwrap();
+    let tag: Tag = xml::serialize::decode(doc.get_root_element().unwrap());
+    assert_eq!(tag._text, ~"red!");
+    let mut map = HashMap::new();
+    map.insert(1u, ~"one");
+    map.insert(20u, ~"twenty");
+    let encoded = xml::serialize::encode(&map, "map", None);
+    assert_eq!(encoded.get_root_element().unwrap().child_elements().next().unwrap().name(), ~"entry");
+    let decoded: HashMap<uint, ~str> = xml::serialize::decode(encoded.get_root_element().unwrap());
+    assert!(decoded == map);
+}
+
+#[test]
+fn test_codegen() {
+    let xsd = "<xs:schema xmlns:xs=\"http://www.w3.org/2001/XMLSchema\" xmlns=\"urn:orders\" targetNamespace=\"urn:orders\">
+                 <xs:simpleType name=\"status\">
+                   <xs:restriction base=\"xs:string\">
+                     <xs:enumeration value=\"open\"/>
+                     <xs:enumeration value=\"in-progress\"/>
+                   </xs:restriction>
+                 </xs:simpleType>
+                 <xs:complexType name=\"item\">
+                   <xs:sequence>
+                     <xs:element name=\"tag\" type=\"xs:string\" maxOccurs=\"unbounded\"/>
+                     <xs:element name=\"note\" type=\"xs:string\" minOccurs=\"0\"/>
+                   </xs:sequence>
+                   <xs:attribute name=\"id\" type=\"xs:unsignedInt\" use=\"required\"/>
+                 </xs:complexType>
+                 <xs:element name=\"order\">
+                   <xs:complexType>
+                     <xs:choice>
+                       <xs:element name=\"item\" type=\"item\"/>
+                       <xs:element name=\"empty\"/>
+                     </xs:choice>
+                     <xs:attribute name=\"status\" type=\"status\"/>
+                   </xs:complexType>
+                 </xs:element>
+               </xs:schema>";
+    let schema = xml::Document::from_str(xsd).unwrap();
+    let code = xml::codegen::generate(&schema).unwrap();
+    assert!(code.contains("pub enum Status {\n    OpenStatus,\n    InProgressStatus\n}"));
+    assert!(code.contains("\"in-progress\" => InProgressStatus,"));
+    assert!(code.contains("pub struct Item {\n    tag: ~[~str],\n    note: Option<~str>,\n    id: u32\n}"));
+    assert!(code.contains("pub struct Order {\n    item: Option<Item>,\n    empty: Option<~str>,\n    status: Option<Status>\n}"));
+    assert!(code.contains("pub fn read_order(element: BorrowedElement) -> Result<Order, DecodeError> {\n    try_decode_unqualified(element)"));
+    assert!(code.contains("encode_unqualified(value, \"order\", Some(\"urn:orders\"))"));
+    let common = TempFile::with_content("xsd",
+        "<xs:schema xmlns:xs=\"http://www.w3.org/2001/XMLSchema\">
+           <xs:complexType name=\"money\">
+             <xs:simpleContent>
+               <xs:extension base=\"xs:decimal\"><xs:attribute name=\"currency\" type=\"xs:string\"/></xs:extension>
+             </xs:simpleContent>
+           </xs:complexType>
+         </xs:schema>");
+    let rates = TempFile::with_content("xsd",
+        "<xs:schema xmlns:xs=\"http://www.w3.org/2001/XMLSchema\" targetNamespace=\"urn:rates\">
+           <xs:simpleType name=\"rate\"><xs:restriction base=\"xs:double\"/></xs:simpleType>
+         </xs:schema>");
+    let prices = TempFile::with_content("xsd", format!(
+        "<xs:schema xmlns:xs=\"http://www.w3.org/2001/XMLSchema\" xmlns=\"urn:prices\" xmlns:r=\"urn:rates\"
+                    targetNamespace=\"urn:prices\">
+           <xs:include schemaLocation=\"{}\"/>
+           <xs:import namespace=\"urn:rates\" schemaLocation=\"{}\"/>
+           <xs:element name=\"price\" type=\"money\"/>
+           <xs:element name=\"discount\" type=\"r:rate\"/>
+         </xs:schema>", common.path.filename_display(), rates.path.filename_display()));
+    let code = xml::codegen::generate(&xml::Document::from_path(&prices.path).unwrap()).unwrap();
+    assert!(code.contains("pub struct Money {\n    text: f64,\n    currency: Option<~str>\n}"));
+    assert!(code.contains("pub type Rate = f64;"));
+    assert!(code.contains("pub fn read_discount(element: BorrowedElement) -> Result<Rate, DecodeError> {"));
+    let qualified = xml::Document::from_str("<xs:schema xmlns:xs=\"http://www.w3.org/2001/XMLSchema\" xmlns=\"urn:a\" targetNamespace=\"urn:a\">
+                                               <xs:element name=\"a\">
+                                                 <xs:complexType><xs:sequence><xs:element ref=\"b\"/></xs:sequence></xs:complexType>
+                                               </xs:element>
+                                               <xs:element name=\"b\" type=\"xs:string\"/>
+                                             </xs:schema>").unwrap();
+    match xml::codegen::generate(&qualified) {
+        Err(err) => assert_eq!(err.message, ~"Unsupported namespace 'urn:a' of element b"),
+        Ok(_) => fail!("Expected unsupported namespace error")
+    }
+    let invalid = xml::Document::from_str("<xs:schema xmlns:xs=\"http://www.w3.org/2001/XMLSchema\">
+                                             <xs:element name=\"a\" type=\"missing\"/>
+                                           </xs:schema>").unwrap();
+    match xml::codegen::generate(&invalid) {
+        Err(err) => assert_eq!(err.line, 2),
+        Ok(_) => fail!("Expected unknown type error")
+    }
+    let colliding = xml::Document::from_str("<xs:schema xmlns:xs=\"http://www.w3.org/2001/XMLSchema\">
+                                               <xs:complexType name=\"orderItem\"><xs:attribute name=\"id\" type=\"xs:int\"/></xs:complexType>
+                                               <xs:element name=\"order\">
+                                                 <xs:complexType><xs:sequence>
+                                                   <xs:element name=\"item\"><xs:complexType><xs:attribute name=\"sku\" type=\"xs:string\"/></xs:complexType></xs:element>
+                                                   <xs:element name=\"named\" type=\"orderItem\"/>
+                                                 </xs:sequence></xs:complexType>
+                                               </xs:element>
+                                             </xs:schema>").unwrap();
+    let code = xml::codegen::generate(&colliding).unwrap();
+    assert!(code.contains("pub struct OrderItem {\n    id: Option<i32>\n}"));
+    assert!(code.contains("pub struct OrderItem_ {\n    sku: Option<~str>\n}"));
+    assert!(code.contains("pub struct Order {\n    item: OrderItem_,\n    named: OrderItem\n}"));
+}
+
+#[test]
+fn test_generated_code() {
+    use orders::{Item, Order, OpenStatus, read_order, write_order};
+    let schema = xml::Document::from_str(include_str!("test/orders.xsd")).unwrap();
+    assert_eq!(xml::codegen::generate(&schema).unwrap().as_slice(), include_str!("test/orders.rs"));
+    let doc = xml::Document::from_str("<order xmlns=\"urn:orders\" status=\"open\">
+                                         <item id=\"1\"><tag>a</tag><tag>b</tag><part id=\"2\"><note>spare</note></part></item>
+                                       </order>").unwrap();
+    let part = Item {tag: ~[], note: Some(~"spare"), part: None, id: 2};
+    let expected = Order {
+        item: ~[Item {tag: ~[~"a", ~"b"], note: None, part: Some(~part), id: 1}],
+        status: Some(OpenStatus)
+    };
+    let order = read_order(doc.get_root_element().unwrap()).unwrap();
+    assert!(order == expected);
+    let written = write_order(&order);
+    assert!(read_order(written.get_root_element().unwrap()).unwrap() == expected);
+}
+
+#[test]
+fn test_diff() {
+    use xml::diff::{DiffOptions, diff_documents, AttributeEdit, UpdateTextEdit, MoveEdit, DeleteEdit};
+    let old = xml::Document::from_str("<a x=\"1\" y=\"2\"><b>one</b><c/><!-- note --><d>keep</d></a>").unwrap();
+    let new = xml::Document::from_str("<a y=\"3\" z=\"4\"><b>two</b>\n<d>keep</d><c/></a>").unwrap();
+    let mut options = DiffOptions::new();
+    options.ignore_whitespace = true;
+    options.ignore_comments = true;
+    assert_eq!(diff_documents(&old, &new, &options), ~[
+        AttributeEdit(~"/a[1]", ~"x", Some(~"1"), None),
+        AttributeEdit(~"/a[1]", ~"y", Some(~"2"), Some(~"3")),
+        AttributeEdit(~"/a[1]", ~"z", None, Some(~"4")),
+        UpdateTextEdit(~"/a[1]/b[1]/text()[1]", ~"one", ~"two"),
+        MoveEdit(~"/a[1]/d[1]", ~"/a[1]/node()[3]")
+    ]);
+    assert!(diff_documents(&old, &new, &DiffOptions::new()).contains(&DeleteEdit(~"/a[1]/comment()[1]")));
+    assert_eq!(diff_documents(&old, &old, &DiffOptions::new()), ~[]);
+    let items = std::vec::from_fn(1100, |i| format!("<i n=\"{}\"/>", i));
+    let long = xml::Document::from_str(format!("<a>{}</a>", items.concat()).as_slice()).unwrap();
+    let shorter = xml::Document::from_str(format!("<a>{}{}</a>", items.slice(0, 500).concat(),
+                                                  items.slice(501, 1100).concat()).as_slice()).unwrap();
+    assert_eq!(diff_documents(&long, &shorter, &DiffOptions::new()), ~[DeleteEdit(~"/a[1]/i[501]")]);
+}
+
+#[test]
+fn test_patch() {
+    let original = "<config xmlns:x=\"urn:x\"><a x:id=\"1\">old</a>\n<b/><!-- c --></config>";
+    let mut doc = xml::Document::from_str(original).unwrap();
+    let patch = xml::Document::from_str("<diff xmlns:y=\"urn:x\">
+                                           <add sel=\"/config/a\" type=\"@name\">v</add>
+                                           <replace sel=\"/config/a/text()\">changed</replace>
+                                           <replace sel=\"/config/a/@y:id\">2</replace>
+                                           <remove sel=\"/config/b\" ws=\"before\"/>
+                                           <add sel=\"/config/a\" pos=\"after\"><c>new</c></add>
+                                         </diff>").unwrap();
+    assert!(doc.apply_patch(&patch).is_ok());
+    let patched = "<?xml version=\"1.0\"?>\n<config xmlns:x=\"urn:x\"><a x:id=\"2\" name=\"v\">changed</a><c>new</c><!-- c --></config>\n";
+    assert_eq!(doc.to_string(), patched.to_owned());
+    let failing = xml::Document::from_str("<diff>
+                                             <remove sel=\"/config/c\"/>
+                                             <remove sel=\"/config/missing\"/>
+                                           </diff>").unwrap();
+    match doc.apply_patch(&failing) {
+        Err(err) => assert_eq!(err.selector, ~"/config/missing"),
+        Ok(_) => fail!("Expected patch to fail")
+    }
+    assert_eq!(doc.to_string(), patched.to_owned());
+    let mut doc = xml::Document::from_str("<?a x?><r/>").unwrap();
+    let patch = xml::Document::from_str("<diff>
+                                           <replace sel=\"/processing-instruction('a')\"><?b y?></replace>
+                                           <add sel=\"/r\" pos=\"before\"> <!-- c --> </add>
+                                           <add sel=\"/r\"><?p q?></add>
+                                         </diff>").unwrap();
+    assert!(doc.apply_patch(&patch).is_ok());
+    assert_eq!(doc.to_string(), ~"<?xml version=\"1.0\"?>\n<?b y?>\n<!-- c -->\n<r><?p q?></r>\n");
+    let text = xml::Document::from_str("<diff><add sel=\"/r\" pos=\"after\">text</add></diff>").unwrap();
+    assert!(doc.apply_patch(&text).is_err());
+}
+
+#[test]
+fn test_tool_support() {
+    use xml::validation::{DtdSchema, Validator, XmlSchema};
+    use xml::xpath::{evaluate, AttributeXPathNode, ChildXPathNode, NodesXPathValue, NumberXPathValue};
+    let doc = xml::Document::from_str("<a  y=\"2\" x=\"1\"><b>text</b>  <!-- c --><b>mixed <i>content</i></b></a>").unwrap();
+    assert_eq!(doc.to_formatted_string(),
+               ~"<?xml version=\"1.0\"?>\n<a y=\"2\" x=\"1\">\n  <b>text</b>\n  <!-- c -->\n  <b>mixed <i>content</i></b>\n</a>\n");
+    assert_eq!(doc.canonicalize(false),
+               "<a x=\"1\" y=\"2\"><b>text</b>  <b>mixed <i>content</i></b></a>".as_bytes().to_owned());
+    match evaluate(&doc, "//b[1]/text() | /a/@x", []) {
+        Ok(NodesXPathValue(nodes)) => {
+            assert_eq!(nodes.len(), 2);
+            match (nodes[0], nodes[1]) {
+                (AttributeXPathNode(attr), ChildXPathNode(text)) => {
+                    assert_eq!(attr.value(), ~"1");
+                    assert_eq!(text.to_string(), ~"text");
+                }
+                _ => fail!("Unexpected nodes")
+            }
+        }
+        _ => fail!("Expected nodes")
+    }
+    match evaluate(&doc, "count(//b)", []) {
+        Ok(NumberXPathValue(count)) => assert_eq!(count, 2.0),
+        _ => fail!("Expected number")
+    }
+    assert!(evaluate(&doc, "//b[", []).is_err());
+    let dtd = TempFile::with_content("dtd", "<!ELEMENT a (b)*><!ELEMENT b (#PCDATA)>");
+    let schema = DtdSchema::from_path(&dtd.path).unwrap();
+    assert!(schema.validate(&xml::Document::from_str("<a><b>x</b></a>").unwrap()).is_ok());
+    assert!(schema.validate(&doc).is_err());
+    let xsd = TempFile::with_content("xsd", "<xs:schema xmlns:xs=\"http://www.w3.org/2001/XMLSchema\">
+                                               <xs:element name=\"a\" type=\"xs:int\"/>
+                                             </xs:schema>");
+    let schema = XmlSchema::from_path(&xsd.path).unwrap();
+    assert!(schema.validate(&xml::Document::from_str("<a>12</a>").unwrap()).is_ok());
+    match schema.validate(&xml::Document::from_str("<a>\n<b/></a>").unwrap()) {
+        Err(errors) => assert_eq!(errors[0].line, 1),
+        Ok(()) => fail!("Expected validation to fail")
+    }
+}
+
+#[test]
+fn test_parse_fragment() {
+    use xml::{NamedNode, TextNode};
+    let doc = xml::Document::from_str("<a xmlns:x=\"urn:x\"><b/></a>").unwrap();
+    let b = doc.get_root_element().unwrap().child_elements().next().unwrap();
+    let nodes = b.parse_fragment("text<x:c x:id=\"1\"/><!-- d -->".as_bytes()).unwrap();
+    assert_eq!(nodes.len(), 3);
+    assert!(nodes[0].get().is_text());
+    match nodes[1].get().get_element() {
+        Some(c) => assert_eq!(c.namespace().map(|ns| ns.href()), Some(~"urn:x")),
+        None => fail!("Expected element")
+    }
+    for node in nodes.move_iter() {
+        assert!(unsafe {b.append_child(node)}.is_ok());
+    }
+    assert_eq!(doc.to_string(),
+               ~"<?xml version=\"1.0\"?>\n<a xmlns:x=\"urn:x\"><b>text<x:c x:id=\"1\"/><!-- d --></b></a>\n");
+    assert!(b.parse_fragment("<y:c/>".as_bytes()).is_err());
+    assert!(b.parse_fragment("<c>".as_bytes()).is_err());
+    assert!(b.parse_fragment("text<?pi?>".as_bytes()).is_err());
+    assert!(b.parse_fragment("<c><d><?pi?></d></c>".as_bytes()).is_err());
+    let entities = xml::Document::from_str("<!DOCTYPE a [<!ENTITY e \"v\">]><a/>").unwrap();
+    let a = entities.get_root_element().unwrap();
+    assert!(a.parse_fragment("<c>&e;</c>".as_bytes()).is_err());
+    assert!(a.parse_fragment("<c x=\"&e;\"/>".as_bytes()).is_err());
+    assert!(a.parse_fragment("<c x=\"&amp;\">&lt;</c>".as_bytes()).is_ok());
+    assert!(b.parse_fragment([116u8, 0xff]).is_err());
+    let latin1 = xml::Document::from_str("<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><a/>").unwrap();
+    let nodes = latin1.get_root_element().unwrap().parse_fragment([99u8, 97, 102, 0xe9]).unwrap();
+    assert_eq!(nodes[0].get().get_text().map(|text| text.content()), Some(~"caf\u00e9"));
+}
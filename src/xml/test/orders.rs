@@ -0,0 +1,101 @@
+// Generated from XML schema by xml::codegen. Do not edit.
+
+#[allow(unused_imports)];
+
+use extra::serialize::{Decodable, Decoder, Encodable, Encoder};
+use xml::{BorrowedElement, Document};
+use xml::serialize;
+use xml::serialize::{DecodeError, try_decode, encode};
+
+#[deriving(Clone, Eq)]
+pub enum Status {
+    OpenStatus,
+    InProgressStatus
+}
+
+impl<E: Encoder> Encodable<E> for Status {
+    fn encode(&self, e: &mut E) {
+        e.emit_str(match *self {
+            OpenStatus => "open",
+            InProgressStatus => "in-progress"
+        })
+    }
+}
+
+impl<'r> Decodable<serialize::Decoder<'r>> for Status {
+    fn decode(d: &mut serialize::Decoder<'r>) -> Status {
+        let value = d.read_str();
+        match value.trim() {
+            "open" => OpenStatus,
+            "in-progress" => InProgressStatus,
+            other => {
+                d.report_error(format!("Invalid Status '{}'", other));
+                OpenStatus
+            }
+        }
+    }
+}
+
+#[deriving(Clone, Eq)]
+pub struct Item {
+    tag: ~[~str],
+    note: Option<~str>,
+    part: Option<~Item>,
+    id: u32
+}
+
+impl<E: Encoder> Encodable<E> for Item {
+    fn encode(&self, e: &mut E) {
+        e.emit_struct("Item", 4, |e| {
+            e.emit_struct_field("tag", 0, |e| self.tag.encode(e));
+            e.emit_struct_field("note", 1, |e| self.note.encode(e));
+            e.emit_struct_field("part", 2, |e| self.part.encode(e));
+            e.emit_struct_field("id", 3, |e| self.id.encode(e));
+        })
+    }
+}
+
+impl<'r> Decodable<serialize::Decoder<'r>> for Item {
+    fn decode(d: &mut serialize::Decoder<'r>) -> Item {
+        d.read_struct("Item", 4, |d| Item {
+            tag: d.read_struct_field("tag", 0, |d| Decodable::decode(d)),
+            note: d.read_struct_field("note", 1, |d| Decodable::decode(d)),
+            part: d.read_struct_field("part", 2, |d| Decodable::decode(d)),
+            id: d.read_struct_field("id", 3, |d| Decodable::decode(d))
+        })
+    }
+}
+
+#[deriving(Clone, Eq)]
+pub struct Order {
+    item: ~[Item],
+    status: Option<Status>
+}
+
+impl<E: Encoder> Encodable<E> for Order {
+    fn encode(&self, e: &mut E) {
+        e.emit_struct("Order", 2, |e| {
+            e.emit_struct_field("item", 0, |e| self.item.encode(e));
+            e.emit_struct_field("status", 1, |e| self.status.encode(e));
+        })
+    }
+}
+
+impl<'r> Decodable<serialize::Decoder<'r>> for Order {
+    fn decode(d: &mut serialize::Decoder<'r>) -> Order {
+        d.read_struct("Order", 2, |d| Order {
+            item: d.read_struct_field("item", 0, |d| Decodable::decode(d)),
+            status: d.read_struct_field("status", 1, |d| Decodable::decode(d))
+        })
+    }
+}
+
+/// Read `order` element.
+pub fn read_order(element: BorrowedElement) -> Result<Order, DecodeError> {
+    try_decode(element)
+}
+
+/// Write `order` element as document.
+pub fn write_order(value: &Order) -> Document {
+    encode(value, "order", Some("urn:orders"))
+}
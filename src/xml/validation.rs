@@ -0,0 +1,211 @@
+/*
+ * Copyright (C) 2014 Maciej Piechotka
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+ * THE SOFTWARE.
+ */
+/*!
+ * Validation of documents against W3C XML Schemas, RELAX NG schemas and
+ * external DTDs.
+ */
+
+use std::ptr::is_null;
+use super::{Document, ParseError};
+use ffi;
+
+/**
+ * Schema against which documents can be validated.
+ */
+pub trait Validator {
+    /**
+     * Validate the document, returning all errors found.
+     */
+    fn validate(&self, doc: &Document) -> Result<(), ~[ParseError]>;
+}
+
+/**
+ * Compiled W3C XML Schema.
+ */
+pub struct XmlSchema {
+    priv schema: *ffi::xmlSchema,
+    // Document the schema was compiled from, which it may refer to
+    priv doc: Option<Document>
+}
+
+impl XmlSchema {
+    /**
+     * Load schema from file, including schemas it imports.
+     */
+    pub fn from_path(path: &Path) -> Result<XmlSchema, ~[ParseError]> {
+        let (schema, errors) = collect_errors(|| unsafe {
+            let ctxt = path.with_c_str(|url| ffi::xmlSchemaNewParserCtxt(url));
+            let schema = ffi::xmlSchemaParse(ctxt);
+            ffi::xmlSchemaFreeParserCtxt(ctxt);
+            schema
+        });
+        if is_null(schema) {
+            Err(or_unknown(errors, "Invalid schema"))
+        } else {
+            Ok(XmlSchema {schema: schema, doc: None})
+        }
+    }
+
+    /**
+     * Compile schema from document. Schemas it imports are loaded relative
+     * to the URL of the document.
+     */
+    pub fn from_document(doc: &Document) -> Result<XmlSchema, ~[ParseError]> {
+        // libxml2 strips comments and whitespace from the document it parses
+        let copy = doc.deep_copy();
+        let (schema, errors) = collect_errors(|| unsafe {
+            let ctxt = ffi::xmlSchemaNewDocParserCtxt(copy.doc);
+            let schema = ffi::xmlSchemaParse(ctxt);
+            ffi::xmlSchemaFreeParserCtxt(ctxt);
+            schema
+        });
+        if is_null(schema) {
+            Err(or_unknown(errors, "Invalid schema"))
+        } else {
+            Ok(XmlSchema {schema: schema, doc: Some(copy)})
+        }
+    }
+}
+
+impl Validator for XmlSchema {
+    fn validate(&self, doc: &Document) -> Result<(), ~[ParseError]> {
+        let (result, errors) = collect_errors(|| unsafe {
+            let ctxt = ffi::xmlSchemaNewValidCtxt(self.schema);
+            let result = ffi::xmlSchemaValidateDoc(ctxt, doc.doc);
+            ffi::xmlSchemaFreeValidCtxt(ctxt);
+            result
+        });
+        if result == 0 {Ok(())} else {Err(or_unknown(errors, "Document is invalid"))}
+    }
+}
+
+impl Drop for XmlSchema {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::xmlSchemaFree(self.schema);
+        }
+    }
+}
+
+/**
+ * Compiled RELAX NG schema in XML syntax.
+ */
+pub struct RelaxNGSchema {
+    priv schema: *ffi::xmlRelaxNG
+}
+
+impl RelaxNGSchema {
+    /**
+     * Load schema from file, including schemas it includes.
+     */
+    pub fn from_path(path: &Path) -> Result<RelaxNGSchema, ~[ParseError]> {
+        let (schema, errors) = collect_errors(|| unsafe {
+            let ctxt = path.with_c_str(|url| ffi::xmlRelaxNGNewParserCtxt(url));
+            let schema = ffi::xmlRelaxNGParse(ctxt);
+            ffi::xmlRelaxNGFreeParserCtxt(ctxt);
+            schema
+        });
+        if is_null(schema) {
+            Err(or_unknown(errors, "Invalid schema"))
+        } else {
+            Ok(RelaxNGSchema {schema: schema})
+        }
+    }
+}
+
+impl Validator for RelaxNGSchema {
+    fn validate(&self, doc: &Document) -> Result<(), ~[ParseError]> {
+        let (result, errors) = collect_errors(|| unsafe {
+            let ctxt = ffi::xmlRelaxNGNewValidCtxt(self.schema);
+            let result = ffi::xmlRelaxNGValidateDoc(ctxt, doc.doc);
+            ffi::xmlRelaxNGFreeValidCtxt(ctxt);
+            result
+        });
+        if result == 0 {Ok(())} else {Err(or_unknown(errors, "Document is invalid"))}
+    }
+}
+
+impl Drop for RelaxNGSchema {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::xmlRelaxNGFree(self.schema);
+        }
+    }
+}
+
+/**
+ * External DTD, used in place of the document type declaration of the
+ * validated documents.
+ */
+pub struct DtdSchema {
+    priv dtd: *ffi::xmlDtd
+}
+
+impl DtdSchema {
+    /**
+     * Load DTD from file.
+     */
+    pub fn from_path(path: &Path) -> Result<DtdSchema, ~[ParseError]> {
+        let (dtd, errors) = collect_errors(|| unsafe {
+            path.with_c_str(|url| ffi::xmlParseDTD(std::ptr::null(), url as *ffi::xmlChar))
+        });
+        if is_null(dtd) {
+            Err(or_unknown(errors, "Invalid DTD"))
+        } else {
+            Ok(DtdSchema {dtd: dtd})
+        }
+    }
+}
+
+impl Validator for DtdSchema {
+    fn validate(&self, doc: &Document) -> Result<(), ~[ParseError]> {
+        let (result, errors) = collect_errors(|| unsafe {
+            let ctxt = ffi::xmlNewValidCtxt();
+            let result = ffi::xmlValidateDtd(ctxt, doc.doc, self.dtd);
+            ffi::xmlFreeValidCtxt(ctxt);
+            result
+        });
+        if result == 1 {Ok(())} else {Err(or_unknown(errors, "Document is invalid"))}
+    }
+}
+
+impl Drop for DtdSchema {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::xmlFreeDtd(self.dtd);
+        }
+    }
+}
+
+// Runs f with errors reported by libxml2 collected instead of ignored.
+fn collect_errors<T>(f: || -> T) -> (T, ~[ParseError]) {
+    let (result, errors) = super::collect_errors(f);
+    (result, errors.move_iter().map(|(_, error)| error).to_owned_vec())
+}
+
+fn or_unknown(errors: ~[ParseError], message: &str) -> ~[ParseError] {
+    if errors.is_empty() {
+        ~[ParseError {message: message.to_owned(), line: 0, column: 0}]
+    } else {
+        errors
+    }
+}
@@ -0,0 +1,114 @@
+/*
+ * Copyright (C) 2014 Maciej Piechotka
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+ * THE SOFTWARE.
+ */
+/*!
+ * Evaluation of XPath 1.0 expressions against documents.
+ */
+
+use std::ptr::is_null;
+use super::{BorrowedAttribute, BorrowedDocument, Document, ElementChild, ParseError};
+use ffi;
+
+/**
+ * Result of an XPath expression.
+ */
+pub enum XPathValue<'r> {
+    /// Nodes in document order.
+    NodesXPathValue(~[XPathNode<'r>]),
+    BooleanXPathValue(bool),
+    NumberXPathValue(f64),
+    StringXPathValue(~str)
+}
+
+/**
+ * Node selected by an XPath expression. Processing instructions are not
+ * represented in the tree and are skipped.
+ */
+pub enum XPathNode<'r> {
+    ChildXPathNode(ElementChild<'r>),
+    AttributeXPathNode(BorrowedAttribute<'r>),
+    /// Namespace node, as prefix and URI.
+    NamespaceXPathNode(Option<~str>, ~str),
+    DocumentXPathNode(BorrowedDocument<'r>)
+}
+
+/**
+ * Evaluate the expression with the document node as context. Prefixes
+ * used in the expression are bound by `namespaces` as (prefix, URI)
+ * pairs.
+ */
+pub fn evaluate<'r>(doc: &'r Document, expr: &str, namespaces: &[(&str, &str)]) -> Result<XPathValue<'r>, ParseError> {
+    super::init();
+    unsafe {
+        let ctxt = ffi::xmlXPathNewContext(doc.doc);
+        if is_null(ctxt) {
+            fail!("Failed to allocate XPath context");
+        }
+        for &(prefix, href) in namespaces.iter() {
+            prefix.with_c_str(|prefix| href.with_c_str(|href| {
+                ffi::xmlXPathRegisterNs(ctxt, prefix as *ffi::xmlChar, href as *ffi::xmlChar);
+            }));
+        }
+        ffi::xmlResetLastError();
+        let result = expr.with_c_str(|expr| ffi::xmlXPathEvalExpression(expr as *ffi::xmlChar, ctxt));
+        ffi::xmlXPathFreeContext(ctxt);
+        if is_null(result) {
+            return Err(super::last_parse_error());
+        }
+        let value = match (*result).otype {
+            1 => NodesXPathValue(nodes((*result).nodesetval)),
+            2 => BooleanXPathValue((*result).boolval != 0),
+            3 => NumberXPathValue((*result).floatval as f64),
+            4 => StringXPathValue(super::owned_str((*result).stringval)),
+            _ => {
+                ffi::xmlXPathFreeObject(result);
+                return Err(ParseError {message: ~"Unsupported result type", line: 0, column: 0});
+            }
+        };
+        ffi::xmlXPathFreeObject(result);
+        Ok(value)
+    }
+}
+
+// Copies the node set, as it does not outlive the XPath object.
+unsafe fn nodes<'r>(set: *ffi::xmlNodeSet) -> ~[XPathNode<'r>] {
+    let mut nodes = ~[];
+    if is_null(set) {
+        return nodes;
+    }
+    for i in range(0, (*set).nodeNr as uint) {
+        let node = *std::ptr::offset((*set).nodeTab, i as int);
+        match (*node)._type {
+            ffi::AttributeNode => nodes.push(AttributeXPathNode(BorrowedAttribute {attr: &*(node as *ffi::xmlAttr)})),
+            ffi::DocumentNode => nodes.push(DocumentXPathNode(BorrowedDocument {doc: &*(node as *ffi::xmlDoc)})),
+            ffi::NamespaceDecl => {
+                let ns = node as *ffi::xmlNs;
+                let prefix = super::ptr_to_option((*ns).prefix).map(|prefix| super::owned_str(prefix));
+                nodes.push(NamespaceXPathNode(prefix, super::owned_str((*ns).href)));
+            }
+            _ => match super::element_child(&*node) {
+                Some(child) => nodes.push(ChildXPathNode(child)),
+                None => {}
+            }
+        }
+    }
+    nodes
+}